@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::Json;
+
+use json::{from_json, to_json};
+use parser::{read_path, recover_path, write_path};
+use property::{Property, PropertyMap};
+use roster;
+use rules;
+use save;
+use skill::SkillDb;
+
+// Read a property file.
+fn load(path: &Path) -> Result<PropertyMap, String> {
+    read_path(path).map_err(|e| format!("Cannot read {:?}: {}", path, e))
+}
+
+// Returns the path of a party member file if an id is given, otherwise of the game file.
+fn target_path(dir: &Path, party: Option<&str>) -> PathBuf {
+    match party {
+        Some(id) => save::party_path(dir, id),
+        None => save::game_path(dir),
+    }
+}
+
+// Print properties one per line; properties of nested records are prefixed with the
+// name of the record.
+fn print_props(props: &PropertyMap, prefix: &str) {
+    for (name, prop) in props {
+        match *prop {
+            Property::Record(ref v) => print_props(v, &format!("{}{}.", prefix, name)),
+            _ => println!("{}{} = {}", prefix, name, prop),
+        }
+    }
+}
+
+/// Prints all properties of the game and companion files.
+pub fn show(dir: &Path) -> Result<(), String> {
+    let path = save::game_path(dir);
+    let game = try!(load(&path));
+    println!("[{}]", path.display());
+    print_props(&game, "");
+    for id in try!(save::companion_ids(dir, &game)) {
+        let path = save::party_path(dir, &id);
+        let member = try!(load(&path));
+        println!("");
+        println!("[{}]", path.display());
+        print_props(&member, "");
+    }
+    Ok(())
+}
+
+/// Prints the value of a property of the game, or of a party member.
+pub fn get(dir: &Path, key: &str, party: Option<&str>) -> Result<(), String> {
+    let path = target_path(dir, party);
+    let props = try!(load(&path));
+    match props.get(key) {
+        Some(prop) => {
+            println!("{}", prop);
+            Ok(())
+        },
+        None => Err(format!("No property {:?} in {:?}", key, path)),
+    }
+}
+
+/// Sets the value of an existing property of the game, or of a party member.
+///
+/// The value is parsed as the same type as the current value. The consistency rules of the
+/// game are then applied to a party member, as on save. The file is backed up before it is
+/// overwritten.
+pub fn set(dir: &Path, key: &str, value: &str, party: Option<&str>, skills: &SkillDb) -> Result<(), String> {
+    let path = target_path(dir, party);
+    try!(save::verify(&path));
+    let mut props = try!(load(&path));
+    let prop = match props.get(key) {
+        Some(prop) => match prop.parse_like(value) {
+            Some(v) => v,
+            None => return Err(format!("Invalid value {:?} for property {:?}", value, key)),
+        },
+        None => return Err(format!("No property {:?} in {:?}", key, path)),
+    };
+    props.insert(key.to_string(), prop);
+    if party.is_some() {
+        rules::normalize(&mut props, skills);
+    }
+    save::write_with_backup(&path, &props, &save::timestamp())
+}
+
+/// Lists the id, name and level of each companion, and whether it is in the party or benched.
+pub fn party(dir: &Path) -> Result<(), String> {
+    let game = try!(load(&save::game_path(dir)));
+    for id in try!(save::companion_ids(dir, &game)) {
+        let member = try!(load(&save::party_path(dir, &id)));
+        let name = member.get("Name").map(|p| p.to_string()).unwrap_or(String::new());
+        let level = member.get("Level").map(|p| p.to_string()).unwrap_or(String::new());
+        let status = if roster::in_party(&game, &member) { "party" } else { "bench" };
+        println!("{}\t{}\t{}\t{}", id, name, level, status);
+    }
+    Ok(())
+}
+
+/// Applies the consistency rules of the game to each companion, as the editor does on save.
+///
+/// Each companion file is backed up before it is overwritten.
+pub fn normalize(dir: &Path, skills: &SkillDb) -> Result<(), String> {
+    let game = try!(load(&save::game_path(dir)));
+    let paths: Vec<PathBuf> = try!(save::companion_ids(dir, &game)).iter()
+        .map(|id| save::party_path(dir, id))
+        .collect();
+    for path in paths.iter() {
+        try!(save::verify(path));
+    }
+    let timestamp = save::timestamp();
+    for path in paths.iter() {
+        let mut member = try!(load(path));
+        rules::normalize(&mut member, skills);
+        try!(save::write_with_backup(path, &member, &timestamp));
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Backs up the game and companion files, printing the path of each backup.
+pub fn backup(dir: &Path) -> Result<(), String> {
+    let timestamp = save::timestamp();
+    let path = save::game_path(dir);
+    let game = try!(load(&path));
+    let mut paths = vec![path];
+    for id in try!(save::companion_ids(dir, &game)) {
+        paths.push(save::party_path(dir, &id));
+    }
+    for path in paths.iter() {
+        println!("{}", try!(save::backup(path, &timestamp)).display());
+    }
+    Ok(())
+}
+
+/// Recovers what can be salvaged from a damaged property file.
+///
+/// If any damage is found, the file is backed up and replaced with a repaired copy.
+pub fn repair(path: &Path) -> Result<(), String> {
+    let recovered = try!(recover_path(path).map_err(|e| format!("Cannot read {:?}: {}", path, e)));
+    if recovered.damaged.is_empty() {
+        println!("No damage found in {:?}.", path);
+        return Ok(());
+    }
+    try!(save::write_with_backup(path, &recovered.props, &save::timestamp()));
+    println!("Recovered {} properties from {:?}; skipped {} damaged regions.",
+             recovered.props.len(), path, recovered.damaged.len());
+    Ok(())
+}
+
+/// Prints the properties of a property file as JSON.
+pub fn dump(path: &Path) -> Result<(), String> {
+    let props = try!(load(path));
+    println!("{}", to_json(&props).pretty());
+    Ok(())
+}
+
+/// Writes properties from a JSON file, as printed by `dump`, to a property file.
+///
+/// If the property file exists, it is backed up before it is overwritten.
+pub fn load_json(json_path: &Path, path: &Path) -> Result<(), String> {
+    let json = {
+        let mut file = try!(File::open(json_path)
+            .map_err(|e| format!("Cannot read {:?}: {}", json_path, e)));
+        try!(Json::from_reader(&mut file).map_err(|e| format!("Cannot read {:?}: {}", json_path, e)))
+    };
+    let props = try!(from_json(&json).map_err(|e| format!("Cannot read {:?}: {}", json_path, e)));
+    if path.exists() {
+        save::write_with_backup(path, &props, &save::timestamp())
+    } else {
+        write_path(path, &props).map_err(|e| format!("Cannot write {:?}: {}", path, e))
+    }
+}
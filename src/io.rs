@@ -13,7 +13,45 @@ pub enum Error {
     Io(io::Error),
     Utf8(string::FromUtf8Error),
     UnexpectedTag(u8),
+    MissingEndTag(u8),
+    /// Declared and expected data length of a record.
+    LengthMismatch(usize, usize),
     UnexpectedEOF,
+    /// An error at a known location in a property file.
+    At(Location, Box<Error>),
+}
+
+impl Error {
+    /// Returns whether the error is caused by damaged data in a property file, rather than by
+    /// failing to read the file at all; only damaged data can be recovered.
+    pub fn is_damage(&self) -> bool {
+        match *self {
+            Error::Io(..) => false,
+            Error::At(_, ref err) => err.is_damage(),
+            _ => true,
+        }
+    }
+}
+
+/// Location of an error in a property file.
+#[derive(Clone, Debug)]
+pub struct Location {
+    /// Byte offset from the start of the file.
+    pub offset: u64,
+    /// Name of the property being decoded, if it was read successfully.
+    pub property: Option<String>,
+    /// Description of the item being decoded.
+    pub expected: &'static str,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "byte {}", self.offset));
+        if let Some(ref name) = self.property {
+            try!(write!(f, " in property \"{}\"", name));
+        }
+        write!(f, ", expected {}", self.expected)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -37,7 +75,12 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::UnexpectedTag(v) => write!(f, "Unexpected tag 0x{:x}.", v),
+            Error::MissingEndTag(v) => write!(f, "Missing end tag, found 0x{:x}.", v),
+            Error::LengthMismatch(declared, expected) => {
+                write!(f, "Data length {} does not match the {} bytes expected.", declared, expected)
+            },
             Error::UnexpectedEOF => write!(f, "Unexpected end of file."),
+            Error::At(ref loc, ref err) => write!(f, "At {}: {}", loc, err),
             Error::Utf8(ref err) => err.fmt(f),
             Error::Io(ref err) => err.fmt(f),
         }
@@ -64,17 +107,13 @@ pub trait ReadVariableExt: Read {
 
     /// Reads a variable-length encoded integer, representing the length of the string; then
     /// reads that many bytes from the underlying reader and interprets them as a utf8 string.
+    ///
+    /// No more is allocated than the reader holds, whatever the length read.
     fn read_variable_string(&mut self) -> Result<String> {
         let len = try!(self.read_variable_uint()) as usize;
-        let mut buf = vec![0; len];
-        let mut nread = 0usize;
-        while nread < buf.len() {
-            match self.read(&mut buf[nread..]) {
-                Ok(0) => return Err(Error::UnexpectedEOF),
-                Ok(n) => nread += n,
-                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
-                Err(e) => return Err(From::from(e))
-            }
+        let mut buf = Vec::new();
+        if try!(self.take(len as u64).read_to_end(&mut buf)) < len {
+            return Err(Error::UnexpectedEOF);
         }
         String::from_utf8(buf).map_err(Error::from)
     }
@@ -83,6 +122,23 @@ pub trait ReadVariableExt: Read {
 /// All types that implement `Read` get methods defined in `ReadVariableExt`.
 impl<R: Read + ?Sized> ReadVariableExt for R {}
 
+/// Returns the number of bytes used by the variable-length encoding of `n`.
+pub fn variable_uint_len(n: u32) -> u32 {
+    let mut val = n;
+    let mut len = 1;
+    while val > 0x7f {
+        val = val >> 7;
+        len += 1;
+    }
+    len
+}
+
+/// Returns the number of bytes used by the length-prefixed encoding of `s`.
+pub fn variable_string_len(s: &str) -> u32 {
+    let len = s.len() as u32;
+    variable_uint_len(len) + len
+}
+
 /// Extension for writing length-prefixed strings.
 pub trait WriteVariableExt: Write {
     /// Writes a variable-length encoded 32-bit integer.
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::mem;
+
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use rustc_serialize::json::Json;
+
+use property::{Property, PropertyMap};
+
+// Encodes a float as a JSON number if it reads back as exactly the same value; otherwise,
+// as for infinities, NaN and values the JSON decoder rounds differently, as a string of its
+// bits in hexadecimal, such as "0xffc00000".
+fn float_to_json<F>(v: f64, bits: String, same: F) -> Json where F: Fn(f64) -> bool {
+    let json = Json::F64(v);
+    match Json::from_str(&json.to_string()).ok().and_then(|j| j.as_f64()) {
+        Some(read) if same(read) => json,
+        _ => Json::String(bits),
+    }
+}
+
+// Returns the bits of a float, as stored in a property file.
+fn f32_bits(v: f32) -> u32 {
+    unsafe { mem::transmute(v) }
+}
+
+fn f64_bits(v: f64) -> u64 {
+    unsafe { mem::transmute(v) }
+}
+
+fn f32_to_json(v: f32) -> Json {
+    let bits = f32_bits(v);
+    float_to_json(v as f64, format!("0x{:08x}", bits), |read| f32_bits(read as f32) == bits)
+}
+
+fn f64_to_json(v: f64) -> Json {
+    let bits = f64_bits(v);
+    float_to_json(v, format!("0x{:016x}", bits), |read| f64_bits(read) == bits)
+}
+
+// Returns the bits of a float encoded as a hexadecimal string, if it is one.
+fn float_bits(json: &Json) -> Option<u64> {
+    match json.as_string() {
+        Some(s) if s.starts_with("0x") => u64::from_str_radix(&s[2..], 16).ok(),
+        _ => None,
+    }
+}
+
+fn f32_from_json(json: &Json) -> Option<f32> {
+    match float_bits(json) {
+        Some(bits) if bits <= u32::max_value() as u64 => {
+            Some(unsafe { mem::transmute::<u32, f32>(bits as u32) })
+        },
+        Some(..) => None,
+        None => f64_from_json(json).map(|v| v as f32),
+    }
+}
+
+fn f64_from_json(json: &Json) -> Option<f64> {
+    match (float_bits(json), json) {
+        (Some(bits), _) => Some(unsafe { mem::transmute::<u64, f64>(bits) }),
+        (None, &Json::String(ref s)) => s.parse::<f64>().ok(),
+        (None, _) => json.as_f64(),
+    }
+}
+
+// Returns the type name and JSON value of a property.
+fn value_to_json(prop: &Property) -> (&'static str, Json) {
+    match *prop {
+        Property::Empty => ("Empty", Json::Null),
+        Property::Boolean(v) => ("Boolean", Json::Boolean(v)),
+        Property::Integer(v) => ("Integer", Json::U64(v as u64)),
+        Property::Long(v) => ("Long", Json::I64(v)),
+        Property::Float(v) => ("Float", f32_to_json(v)),
+        Property::Double(v) => ("Double", f64_to_json(v)),
+        Property::String(ref v) => ("String", Json::String(v.clone())),
+        Property::List(ref v) => {
+            ("List", Json::Array(v.iter().map(|s| Json::String(s.clone())).collect()))
+        },
+        Property::Bytes(ref v) => ("Bytes", Json::String(v.to_base64(base64::STANDARD))),
+        Property::Record(ref v) => ("Record", to_json(v)),
+        Property::Unknown(ref v, _) => ("Unknown", Json::String(v.to_base64(base64::STANDARD))),
+    }
+}
+
+// Returns the property of the given type name with a JSON value, or `None` if the value
+// is not valid for that type.
+fn value_from_json(ty: &str, value: &Json, tag: Option<u64>) -> Option<Property> {
+    match ty {
+        "Empty" => if value.is_null() { Some(Property::Empty) } else { None },
+        "Boolean" => value.as_boolean().map(Property::Boolean),
+        "Integer" => value.as_u64()
+            .and_then(|v| if v <= u32::max_value() as u64 { Some(v as u32) } else { None })
+            .map(Property::Integer),
+        "Long" => value.as_i64().map(Property::Long),
+        "Float" => f32_from_json(value).map(Property::Float),
+        "Double" => f64_from_json(value).map(Property::Double),
+        "String" => value.as_string().map(Property::from),
+        "List" => value.as_array().and_then(|v| {
+            v.iter()
+                .map(|s| s.as_string().map(String::from))
+                .collect::<Option<Vec<String>>>()
+                .map(Property::List)
+        }),
+        "Bytes" => value.as_string()
+            .and_then(|s| s.from_base64().ok())
+            .map(Property::Bytes),
+        "Record" => from_json(value).ok().map(Property::Record),
+        "Unknown" => match (value.as_string().and_then(|s| s.from_base64().ok()), tag) {
+            (Some(v), Some(tag)) if tag <= 0xff => Some(Property::Unknown(v, tag as u8)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Converts properties to JSON.
+///
+/// The result is an array with an object for each property, in order, holding its `name`,
+/// the `type` of property, and its `value`. Floats that a JSON number would not keep exactly
+/// are encoded as a hexadecimal string of their bits, byte data as base64, and the data type
+/// tag of unknown properties is kept as `tag`.
+pub fn to_json(props: &PropertyMap) -> Json {
+    Json::Array(props.iter().map(|(name, prop)| {
+        let (ty, value) = value_to_json(prop);
+        let mut obj = BTreeMap::new();
+        obj.insert("name".to_string(), Json::String(name.clone()));
+        obj.insert("type".to_string(), Json::String(ty.to_string()));
+        obj.insert("value".to_string(), value);
+        if let Property::Unknown(_, tag) = *prop {
+            obj.insert("tag".to_string(), Json::U64(tag as u64));
+        }
+        Json::Object(obj)
+    }).collect())
+}
+
+/// Converts JSON produced by `to_json` back to properties.
+pub fn from_json(json: &Json) -> Result<PropertyMap, String> {
+    let items = try!(json.as_array().ok_or("Expected an array of properties.".to_string()));
+    let mut props = PropertyMap::new();
+    for (i, item) in items.iter().enumerate() {
+        let name = try!(item.find("name").and_then(|v| v.as_string())
+            .ok_or(format!("Property {}: expected a \"name\" string.", i)));
+        let ty = try!(item.find("type").and_then(|v| v.as_string())
+            .ok_or(format!("Property {:?}: expected a \"type\" string.", name)));
+        let value = try!(item.find("value")
+            .ok_or(format!("Property {:?}: expected a \"value\".", name)));
+        let tag = item.find("tag").and_then(|v| v.as_u64());
+        let prop = try!(value_from_json(ty, value, tag)
+            .ok_or(format!("Property {:?}: invalid value for type {:?}.", name, ty)));
+        props.push(name.to_string(), prop);
+    }
+    Ok(props)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{f32, f64, mem};
+
+    use rustc_serialize::json::Json;
+
+    use parser::tests::sample;
+    use property::{Property, PropertyMap};
+    use super::*;
+
+    // Returns the bits of the floats of a property, to compare NaN and signed zeros.
+    fn bits(prop: &Property) -> Option<u64> {
+        match *prop {
+            Property::Float(v) => Some(f32_bits(v) as u64),
+            Property::Double(v) => Some(f64_bits(v)),
+            _ => None,
+        }
+    }
+
+    // Dumps properties to JSON text and loads them back.
+    fn round_trip(props: &PropertyMap) -> PropertyMap {
+        let text = to_json(props).to_string();
+        from_json(&Json::from_str(&text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_sample() {
+        let props = sample();
+        let loaded = round_trip(&props);
+        assert_eq!(loaded.len(), props.len());
+        for ((name, prop), (loaded_name, loaded_prop)) in props.iter().zip(loaded.iter()) {
+            assert_eq!(name, loaded_name);
+            assert_eq!(format!("{:?}", prop), format!("{:?}", loaded_prop));
+        }
+    }
+
+    #[test]
+    fn round_trip_floats() {
+        let floats: Vec<f32> = vec![
+            0.0, -0.0, 0.1, 1.0 / 3.0, f32::MAX, f32::MIN_POSITIVE, 1e-45,
+            f32::INFINITY, f32::NEG_INFINITY, f32::NAN,
+            unsafe { mem::transmute::<u32, f32>(0xffc00000) },
+        ];
+        let doubles: Vec<f64> = vec![
+            0.0, -0.0, 0.1, 1.0 / 3.0, 123456.789012345678, f64::MAX, f64::MIN_POSITIVE, 5e-324,
+            2.2250738585072011e-308, f64::INFINITY, f64::NEG_INFINITY, f64::NAN,
+            unsafe { mem::transmute::<u64, f64>(0xfff8000000000001) },
+        ];
+        let mut props = PropertyMap::new();
+        for &v in floats.iter() {
+            props.push("Float".to_string(), Property::Float(v));
+        }
+        for &v in doubles.iter() {
+            props.push("Double".to_string(), Property::Double(v));
+        }
+        let loaded = round_trip(&props);
+        assert_eq!(loaded.len(), props.len());
+        for ((_, prop), (_, loaded_prop)) in props.iter().zip(loaded.iter()) {
+            assert_eq!(bits(prop), bits(loaded_prop), "{:?} read back as {:?}", prop, loaded_prop);
+        }
+    }
+
+    #[test]
+    fn exact_floats_are_numbers() {
+        let mut props = PropertyMap::new();
+        props.push("Str".to_string(), Property::Float(2.5));
+        match to_json(&props)[0].find("value") {
+            Some(&Json::F64(v)) => assert_eq!(v, 2.5),
+            value => panic!("expected a number, found {:?}", value),
+        }
+    }
+}
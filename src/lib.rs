@@ -1,12 +1,30 @@
 #[macro_use]
 extern crate byteorder;
 extern crate iup;
+extern crate rustc_serialize;
 extern crate time;
 extern crate xml;
 
-mod io;
-mod parser;
-mod property;
+// Writes a diagnostic message to stderr, keeping stdout for command output.
+macro_rules! log {
+    ($($arg:tt)*) => ({
+        use std::io::Write;
+        let _ = writeln!(&mut ::std::io::stderr(), $($arg)*);
+    })
+}
+
+pub mod cli;
+pub mod io;
+pub mod json;
+pub mod parser;
+pub mod points;
+pub mod property;
+pub mod race;
+pub mod roster;
+pub mod rules;
+pub mod skill;
+
+mod save;
 
 pub use ui::{ui_loop};
 
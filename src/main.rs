@@ -1,16 +1,73 @@
 extern crate rustc_serialize;
 extern crate docopt;
 
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
 use docopt::Docopt;
 
 // Docopt usage string.
 static USAGE: &'static str = r#"
-Usage: sits [<dir>]
+Serpent in the Staglands save game editor.
+
+Without a command, opens the editor and asks you to select a save game; open
+loads the save game in <dir> directly.
+
+Usage:
+  sits [--skills=<path>]
+  sits open <dir> [--skills=<path>]
+  sits show <dir>
+  sits get <dir> <key> [--party=<id>]
+  sits set <dir> <key> <value> [--party=<id>] [--skills=<path>]
+  sits party <dir>
+  sits normalize <dir> [--skills=<path>]
+  sits backup <dir>
+  sits repair <file>
+  sits dump <file>
+  sits load <json> <file>
+  sits (-h | --help)
+
+Commands:
+  open      Open the editor on a save game.
+  show      Print all properties of the game and companions.
+  get       Print the value of a property.
+  set       Change the value of an existing property, keeping its type; grades
+            and known skills of a companion are recomputed, as on save.
+  party     List the id, name and level of each companion, in party or benched.
+  normalize Recompute grades and known skills of each companion, as on save.
+  backup    Make timestamped backups of the game and companion files.
+  repair    Recover what can be salvaged from a damaged property file.
+  dump      Print a property file as JSON.
+  load      Write a property file from JSON, as printed by dump.
+
+Options:
+  -h --help        Show this message.
+  --party=<id>     Use the party member file Party<id>.txt instead of Game.txt.
+  --skills=<path>  Read skills from a skills.xml file, or from the first one
+                   found in a directory such as the game install, instead of
+                   the built-in skills.
 "#;
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
+    cmd_open: bool,
+    cmd_show: bool,
+    cmd_get: bool,
+    cmd_set: bool,
+    cmd_party: bool,
+    cmd_normalize: bool,
+    cmd_backup: bool,
+    cmd_repair: bool,
+    cmd_dump: bool,
+    cmd_load: bool,
     arg_dir: String,
+    arg_key: String,
+    arg_value: String,
+    arg_file: String,
+    arg_json: String,
+    flag_party: Option<String>,
+    flag_skills: Option<String>,
 }
 
 extern crate sits;
@@ -20,8 +77,40 @@ fn main() {
         .and_then(|d| d.decode())
         .unwrap_or_else(|e| e.exit());
 
-    match sits::ui_loop() {
-        Err(e) => println!("{}", e),
+    let dir = Path::new(&args.arg_dir);
+    let party = args.flag_party.as_ref().map(|s| &s[..]);
+    let skills = args.flag_skills.as_ref().map(|s| Path::new(s));
+    let res = if args.cmd_show {
+        sits::cli::show(dir)
+    } else if args.cmd_get {
+        sits::cli::get(dir, &args.arg_key, party)
+    } else if args.cmd_set {
+        sits::skill::SkillDb::load(skills)
+            .and_then(|skills| sits::cli::set(dir, &args.arg_key, &args.arg_value, party, &skills))
+    } else if args.cmd_party {
+        sits::cli::party(dir)
+    } else if args.cmd_normalize {
+        sits::skill::SkillDb::load(skills).and_then(|skills| sits::cli::normalize(dir, &skills))
+    } else if args.cmd_backup {
+        sits::cli::backup(dir)
+    } else if args.cmd_repair {
+        sits::cli::repair(Path::new(&args.arg_file))
+    } else if args.cmd_dump {
+        sits::cli::dump(Path::new(&args.arg_file))
+    } else if args.cmd_load {
+        sits::cli::load_json(Path::new(&args.arg_json), Path::new(&args.arg_file))
+    } else if args.cmd_open {
+        sits::skill::SkillDb::load(skills)
+            .and_then(|skills| sits::ui_loop(Some(args.arg_dir.clone()), skills))
+    } else {
+        sits::skill::SkillDb::load(skills).and_then(|skills| sits::ui_loop(None, skills))
+    };
+
+    match res {
+        Err(e) => {
+            writeln!(&mut io::stderr(), "{}", e).unwrap();
+            process::exit(1);
+        },
         _ => {}
     };
 }
@@ -1,11 +1,30 @@
+use std::cmp;
+use std::fmt;
 use std::fs::{File};
-use std::io::{BufReader, BufRead, Read, BufWriter, Write};
+use std::io::{self, BufReader, Read, BufWriter, Write};
 use std::path::Path;
 
 use byteorder::{self, LittleEndian, ReadBytesExt, WriteBytesExt};
-use io::{Error, Result, ReadVariableExt, WriteVariableExt};
+use io::{Error, Location, Result, ReadVariableExt, WriteVariableExt, variable_string_len, variable_uint_len};
 use property::{Property, PropertyMap};
 
+// Data type tags.
+//
+// Only string, int, float and bool are known to appear in save games. The other tags are
+// speculative: their values and layouts are guessed from the BinaryWriter layout of the
+// corresponding .NET type, and no save game containing them has been seen. Byte arrays
+// are assumed to be length-prefixed like strings, and records to contain a nested sequence
+// of properties. Payloads that do not encode back to exactly the same bytes are kept as
+// unknown data, so a wrong guess cannot corrupt a file.
+const TAG_STRING: u8 = 0x01;
+const TAG_INT: u8 = 0x02;
+const TAG_LONG: u8 = 0x04;
+const TAG_FLOAT: u8 = 0x06;
+const TAG_DOUBLE: u8 = 0x07;
+const TAG_BOOL: u8 = 0x09;
+const TAG_BYTES: u8 = 0x0b;
+const TAG_RECORD: u8 = 0x0c;
+
 static LIST_PROPERTIES: [&'static str; 5] = [
     "CombatSelects",
     "CombatSkills",
@@ -16,111 +35,713 @@ static LIST_PROPERTIES: [&'static str; 5] = [
 
 /// Reads a property file.
 pub fn read_path(path: &Path) -> Result<PropertyMap> {
-    println!("Reading {:?}", path);
+    log!("Reading {:?}", path);
 
     let file = try!(File::open(path));
-    let mut buf = BufReader::new(&file);
-    let mut res = PropertyMap::new();
-    loop {
-        let done = try!(match buf.read_u8() {
-            Ok(0x7e) => Ok(false),
-            Ok(v) => Err(Error::UnexpectedTag(v)),
-            Err(byteorder::Error::UnexpectedEOF) => Ok(true),
-            Err(e) => Err(Error::from(e)),
-        });
-        if done {
-            break;
+    PropertyReader::new(BufReader::new(file)).collect()
+}
+
+/// Writes a property file, in the order the properties appear in `props`.
+pub fn write_path(path: &Path, props: &PropertyMap) -> Result<()> {
+    log!("Writing {:?}", path);
+
+    let file = try!(File::create(path));
+    let mut writer = PropertyWriter::new(BufWriter::new(file));
+    try!(writer.write_props(props));
+    try!(writer.get_mut().flush());
+    Ok(())
+}
+
+/// A region of a property file that could not be decoded.
+#[derive(Debug)]
+pub struct Damage {
+    /// Byte offset of the start of the region.
+    pub start: u64,
+    /// Byte offset of the end of the region, exclusive.
+    pub end: u64,
+    /// The error that caused the region to be skipped.
+    pub error: Error,
+}
+
+/// Properties salvaged from a damaged property file.
+#[derive(Debug)]
+pub struct Recovered {
+    pub props: PropertyMap,
+    pub damaged: Vec<Damage>,
+}
+
+/// Reads a property file leniently; see `recover`.
+pub fn recover_path(path: &Path) -> Result<Recovered> {
+    log!("Recovering {:?}", path);
+
+    let mut data = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut data));
+    let res = recover(&data);
+    for damage in res.damaged.iter() {
+        log!("Skipped bytes {}..{}: {}", damage.start, damage.end, damage.error);
+    }
+    Ok(res)
+}
+
+/// Reads as many properties as possible from damaged property data.
+///
+/// Records which cannot be decoded are skipped. If a record cannot be framed, reading resumes
+/// at the next 0x7e marker from which a valid record can be read.
+pub fn recover(data: &[u8]) -> Recovered {
+    let mut res = Recovered { props: PropertyMap::new(), damaged: Vec::new() };
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut reader = PropertyReader::new(&data[pos..]);
+        loop {
+            let start = pos + reader.offset() as usize;
+            match reader.read_property() {
+                Ok(Some((name, prop))) => res.props.push(name, prop),
+                Ok(None) => return res,
+                Err(e) => {
+                    let end = if reader.is_in_sync() {
+                        pos + reader.offset() as usize
+                    } else {
+                        resync(data, start + 1)
+                    };
+                    res.damaged.push(Damage { start: start as u64, end: end as u64, error: e });
+                    if !reader.is_in_sync() {
+                        pos = end;
+                        break;
+                    }
+                },
+            }
         }
+    }
+    res
+}
+
+// Returns the offset of the next plausible record at or after `pos`, or the end of the data.
+fn resync(data: &[u8], pos: usize) -> usize {
+    (pos..data.len())
+        .find(|&i| {
+            data[i] == 0x7e && match PropertyReader::new(&data[i..]).read_property() {
+                Ok(Some(..)) => true,
+                _ => false,
+            }
+        })
+        .unwrap_or(data.len())
+}
+
+/// Returns the data type tag used to encode a property, or `None` if it has no data.
+pub fn data_type(prop: &Property) -> Option<u8> {
+    match *prop {
+        Property::Empty => None,
+        Property::Boolean(..) => Some(TAG_BOOL),
+        Property::Integer(..) => Some(TAG_INT),
+        Property::Long(..) => Some(TAG_LONG),
+        Property::Float(..) => Some(TAG_FLOAT),
+        Property::Double(..) => Some(TAG_DOUBLE),
+        Property::String(..) | Property::List(..) => Some(TAG_STRING),
+        Property::Bytes(..) => Some(TAG_BYTES),
+        Property::Record(..) => Some(TAG_RECORD),
+        Property::Unknown(_, data_type) => Some(data_type),
+    }
+}
+
+/// A pull parser over a stream of property records.
+///
+/// Yields `(name, property)` pairs one at a time, in the order they appear in the stream.
+/// Reads are small; wrap unbuffered sources such as `File` in a `BufReader`.
+pub struct PropertyReader<R> {
+    inner: Counter<R>,
+    in_sync: bool,
+    done: bool,
+}
+
+impl<R: Read> PropertyReader<R> {
+    pub fn new(inner: R) -> Self {
+        PropertyReader { inner: Counter { inner: inner, count: 0 }, in_sync: true, done: false }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner.inner
+    }
+
+    /// Returns whether the stream is positioned at a record boundary, which is not the case
+    /// after an error in the framing of a record.
+    pub fn is_in_sync(&self) -> bool {
+        self.in_sync
+    }
+
+    /// Returns the number of bytes consumed from the stream so far.
+    pub fn offset(&self) -> u64 {
+        self.inner.count
+    }
+
+    /// Reads the next property record, or returns `None` at the end of the stream.
+    ///
+    /// Errors are wrapped in `Error::At`, giving the location of the failed read. If the
+    /// record was framed correctly but its data could not be decoded, the record is skipped
+    /// and reading may continue with the next one; see `is_in_sync`.
+    pub fn read_property(&mut self) -> Result<Option<(String, Property)>> {
+        let mut loc = Location { offset: self.offset(), property: None, expected: "" };
+        self.in_sync = false;
+        match self.read_record(&mut loc) {
+            Err(e) => Err(Error::At(loc, Box::new(e))),
+            v => v,
+        }
+    }
+
+    // Reads a record, keeping `loc` pointed at the item being decoded.
+    fn read_record(&mut self, loc: &mut Location) -> Result<Option<(String, Property)>> {
+        let buf = &mut self.inner;
+
+        loc.expected = "record tag 0x7e";
+        match buf.read_u8() {
+            Ok(0x7e) => {},
+            Ok(v) => return Err(Error::UnexpectedTag(v)),
+            Err(byteorder::Error::UnexpectedEOF) => {
+                self.in_sync = true;
+                return Ok(None);
+            },
+            Err(e) => return Err(Error::from(e)),
+        };
+        loc.offset = buf.count;
+        loc.expected = "property name";
         let name = try!(buf.read_variable_string());
         let name_is_list = LIST_PROPERTIES.contains(&name.as_ref());
+        loc.property = Some(name.clone());
+
+        loc.offset = buf.count;
+        loc.expected = "data length";
         let data_len = try!(buf.read_u32::<LittleEndian>()) as usize;
-        if data_len > 0 {
-            let data_type = try!(buf.read_u8());
-            res.insert(name, match data_type {
-                0x01 => {
-                    let s = try!(buf.read_variable_string());
-                    if name_is_list {
-                        Property::from(s.split(",").map(String::from).collect::<Vec<String>>())
-                    } else {
-                        Property::from(s)
-                    }
-                },
-                0x02 => Property::from(try!(buf.read_u32::<LittleEndian>())),
-                0x06 => Property::from(try!(buf.read_f32::<LittleEndian>())),
-                0x09 => Property::from(try!(buf.read_u8()) != 0),
-                _    => {
-                    let mut v = vec![0; data_len - 2];
-                    try!(buf.read(&mut v));
-                    Property::Unknown(v, data_type)
-                }
-            });
-            buf.consume(1); // Consume end tag 0x7b
+        if data_len == 0 {
+            self.in_sync = true;
+            return Ok(Some((name, Property::Empty)));
+        }
+
+        // The declared length frames the record; read all of it before decoding, so that the
+        // stream stays in sync with the next record even if the data turns out to be invalid.
+        loc.offset = buf.count;
+        loc.expected = "record data";
+        let mut data = Vec::new();
+        if try!(buf.by_ref().take(data_len as u64).read_to_end(&mut data)) < data_len {
+            return Err(Error::UnexpectedEOF);
+        }
+        self.in_sync = true;
+        if data_len < 2 {
+            return Err(Error::LengthMismatch(data_len, 2));
         }
+
+        loc.offset = buf.count - 1;
+        loc.expected = "end tag 0x7b";
+        match data.pop() {
+            Some(0x7b) => {},
+            Some(v) => return Err(Error::MissingEndTag(v)),
+            None => unreachable!(),
+        }
+        let data_type = data.remove(0);
+
+        loc.offset = buf.count - data_len as u64 + 1;
+        loc.expected = match data_type {
+            TAG_STRING => "string data",
+            TAG_INT => "integer data",
+            TAG_FLOAT => "float data",
+            TAG_BOOL => "boolean data",
+            _ => "data",
+        };
+        let prop = try!(decode_data(data_type, data, name_is_list));
+        Ok(Some((name, prop)))
     }
-    Ok(res)
 }
 
-/// Writes a property file.
-pub fn write_path(path: &Path, props: &PropertyMap) -> Result<()> {
-    println!("Writing {:?}", path);
+// Decodes the data of a record, which must be exactly the size implied by its type tag.
+fn decode_data(data_type: u8, data: Vec<u8>, name_is_list: bool) -> Result<Property> {
+    Ok(match data_type {
+        TAG_STRING => {
+            let start = {
+                let mut buf = &data[..];
+                let len = try!(buf.read_variable_uint()) as usize;
+                try!(expect_len(&data, data.len() - buf.len() + len));
+                data.len() - len
+            };
+            let s = try!(String::from_utf8(data[start..].to_vec()));
+            if name_is_list {
+                Property::from(s.split(",").map(String::from).collect::<Vec<String>>())
+            } else {
+                Property::from(s)
+            }
+        },
+        TAG_INT => {
+            try!(expect_len(&data, 4));
+            Property::from(try!((&data[..]).read_u32::<LittleEndian>()))
+        },
+        TAG_FLOAT => {
+            try!(expect_len(&data, 4));
+            Property::from(try!((&data[..]).read_f32::<LittleEndian>()))
+        },
+        TAG_BOOL => {
+            try!(expect_len(&data, 1));
+            Property::from(data[0] != 0)
+        },
+        _ => decode_payload(data_type, data),
+    })
+}
 
-    let file = try!(File::create(path));
-    let mut buf = BufWriter::new(&file);
-    for (name, prop) in props {
-        try!(buf.write_u8(0x7e));
-        try!(buf.write_variable_string(name));
+// Checks the size of record data against the size implied by its type.
+fn expect_len(data: &[u8], expected: usize) -> Result<()> {
+    if data.len() == expected {
+        Ok(())
+    } else {
+        // Report lengths as declared, including the type and end tags
+        Err(Error::LengthMismatch(data.len() + 2, expected + 2))
+    }
+}
+
+impl<R: Read> Iterator for PropertyReader<R> {
+    type Item = Result<(String, Property)>;
+
+    /// Stops after the end of the stream or the first error that leaves it out of sync.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_property() {
+            Ok(Some(v)) => Some(Ok(v)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = !self.in_sync;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+/// A writer of property records, the counterpart to `PropertyReader`.
+pub struct PropertyWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> PropertyWriter<W> {
+    pub fn new(inner: W) -> Self {
+        PropertyWriter { inner: inner }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes all properties, in order.
+    pub fn write_props(&mut self, props: &PropertyMap) -> Result<()> {
+        for (name, prop) in props {
+            try!(self.write_property(name, prop));
+        }
+        Ok(())
+    }
+
+    /// Writes a single property record.
+    pub fn write_property(&mut self, name: &str, prop: &Property) -> Result<()> {
+        try!(self.inner.write_u8(0x7e));
+        try!(self.inner.write_variable_string(name));
+        self.write_data(prop)
+    }
+
+    /// Writes the data length, type tag, data and end tag of a property record.
+    fn write_data(&mut self, prop: &Property) -> Result<()> {
+        let buf = &mut self.inner;
+        // Data length covers the type tag, the data, and the end tag
         match *prop {
+            Property::Empty => {
+                try!(buf.write_u32::<LittleEndian>(0));
+                return Ok(()); // No type or end tag
+            },
             Property::Boolean(v) => {
                 try!(buf.write_u32::<LittleEndian>(2 + 1));
-                try!(buf.write_u8(0x09));
+                try!(buf.write_u8(TAG_BOOL));
                 try!(buf.write_u8(if v { 1 } else { 0 }));
             },
             Property::Integer(v) => {
                 try!(buf.write_u32::<LittleEndian>(2 + 4));
-                try!(buf.write_u8(0x02));
+                try!(buf.write_u8(TAG_INT));
                 try!(buf.write_u32::<LittleEndian>(v));
             },
+            Property::Long(v) => {
+                try!(buf.write_u32::<LittleEndian>(2 + 8));
+                try!(buf.write_u8(TAG_LONG));
+                try!(buf.write_i64::<LittleEndian>(v));
+            },
             Property::Float(v) => {
                 try!(buf.write_u32::<LittleEndian>(2 + 4));
-                try!(buf.write_u8(0x06));
+                try!(buf.write_u8(TAG_FLOAT));
                 try!(buf.write_f32::<LittleEndian>(v));
             },
+            Property::Double(v) => {
+                try!(buf.write_u32::<LittleEndian>(2 + 8));
+                try!(buf.write_u8(TAG_DOUBLE));
+                try!(buf.write_f64::<LittleEndian>(v));
+            },
             Property::String(ref v) => {
-                let len = v.len() as u32;
-                try!(buf.write_u32::<LittleEndian>(2 + match len.leading_zeros() {
-                     1 ...  3 => 5 + len,
-                     4 ... 10 => 4 + len,
-                    11 ... 17 => 3 + len,
-                    18 ... 24 => 2 + len,
-                    25 ... 32 => 1 + len,
-                    _ => panic!("string length exceeds maximum 2^31")
-                }));
-                try!(buf.write_u8(0x01));
+                try!(buf.write_u32::<LittleEndian>(2 + variable_string_len(v)));
+                try!(buf.write_u8(TAG_STRING));
                 try!(buf.write_variable_string(v));
             },
             Property::List(..) => {
                 let s = prop.to_string();
-                let len = s.len() as u32;
-                try!(buf.write_u32::<LittleEndian>(2 + match len.leading_zeros() {
-                     1 ...  3 => 5 + len,
-                     4 ... 10 => 4 + len,
-                    11 ... 17 => 3 + len,
-                    18 ... 24 => 2 + len,
-                    25 ... 32 => 1 + len,
-                    _ => panic!("string length exceeds maximum 2^31")
-                }));
-                try!(buf.write_u8(0x01));
+                try!(buf.write_u32::<LittleEndian>(2 + variable_string_len(&s)));
+                try!(buf.write_u8(TAG_STRING));
                 try!(buf.write_variable_string(&s));
             },
-            Property::Unknown(ref v, data_type) => {
+            Property::Bytes(ref v) => {
                 let len = v.len() as u32;
-                try!(buf.write_u32::<LittleEndian>(2 + len));
+                try!(buf.write_u32::<LittleEndian>(2 + variable_uint_len(len) + len));
+                try!(buf.write_u8(TAG_BYTES));
+                try!(buf.write_variable_uint(len));
+                try!(buf.write_all(v));
+            },
+            Property::Record(ref v) => {
+                let mut nested = PropertyWriter::new(Vec::new());
+                try!(nested.write_props(v));
+                let data = nested.into_inner();
+                try!(buf.write_u32::<LittleEndian>(2 + data.len() as u32));
+                try!(buf.write_u8(TAG_RECORD));
+                try!(buf.write_all(&data));
+            },
+            Property::Unknown(ref v, data_type) => {
+                try!(buf.write_u32::<LittleEndian>(2 + v.len() as u32));
                 try!(buf.write_u8(data_type));
                 try!(buf.write_all(v));
             },
         };
         try!(buf.write_u8(0x7b));
+        Ok(())
+    }
+}
+
+/// Decodes the payload of a record with one of the speculative data types.
+///
+/// Payloads with an unrecognized tag, or which do not encode back to exactly the same bytes,
+/// are kept as `Property::Unknown`.
+fn decode_payload(data_type: u8, payload: Vec<u8>) -> Property {
+    let prop = {
+        let mut buf = &payload[..];
+        match data_type {
+            TAG_LONG => buf.read_i64::<LittleEndian>().ok().map(Property::Long),
+            TAG_DOUBLE => buf.read_f64::<LittleEndian>().ok().map(Property::Double),
+            TAG_BYTES => buf.read_variable_uint().ok().map(|len| {
+                Property::Bytes(buf.iter().take(len as usize).cloned().collect())
+            }),
+            TAG_RECORD => PropertyReader::new(buf).collect::<Result<PropertyMap>>().ok()
+                .map(Property::Record),
+            _ => None,
+        }
+    };
+    match prop {
+        Some(prop) => {
+            let mut writer = PropertyWriter::new(Vec::with_capacity(payload.len() + 6));
+            let exact = writer.write_data(&prop).is_ok() && {
+                // Skip the data length and type tag, and the end tag
+                let data = writer.get_ref();
+                data.len() == payload.len() + 6 && &data[5..data.len() - 1] == &payload[..]
+            };
+            if exact { prop } else { Property::Unknown(payload, data_type) }
+        },
+        None => Property::Unknown(payload, data_type),
     }
-    Ok(())
 }
 
+// Counts the bytes read from the underlying reader.
+struct Counter<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for Counter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Location of the first difference between a property file and its re-encoding.
+#[derive(Debug)]
+pub struct Mismatch {
+    /// Byte offset of the first differing byte.
+    pub offset: usize,
+    /// Name of the property whose record contains the offset.
+    pub name: Option<String>,
+    /// Data type tag of that property.
+    pub data_type: Option<u8>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "Re-encoding differs at byte {}", self.offset));
+        if let Some(ref name) = self.name {
+            try!(write!(f, " in property \"{}\"", name));
+        }
+        if let Some(data_type) = self.data_type {
+            try!(write!(f, " (tag 0x{:02x})", data_type));
+        }
+        write!(f, ".")
+    }
+}
+
+/// Verifies that a property file survives a round-trip through `read_path` and `write_path`.
+///
+/// The file is decoded and re-encoded in memory, without writing anything to disk.
+/// Returns the location of the first difference, or `None` if the encoding is identical.
+pub fn verify_path(path: &Path) -> Result<Option<Mismatch>> {
+    log!("Verifying {:?}", path);
+
+    let mut orig = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut orig));
+    let props: PropertyMap = try!(PropertyReader::new(&orig[..]).collect());
+
+    let mut writer = PropertyWriter::new(Vec::with_capacity(orig.len()));
+    let mut records = Vec::with_capacity(props.len());
+    for (name, prop) in &props {
+        records.push((writer.get_ref().len(), name, prop));
+        try!(writer.write_property(name, prop));
+    }
+    let encoded = writer.into_inner();
+
+    let offset = match orig.iter().zip(encoded.iter()).position(|(a, b)| a != b) {
+        Some(i) => i,
+        None if orig.len() != encoded.len() => cmp::min(orig.len(), encoded.len()),
+        None => return Ok(None),
+    };
+    let record = records.iter().rev().find(|r| r.0 <= offset);
+    Ok(Some(Mismatch {
+        offset: offset,
+        name: record.map(|r| r.1.to_string()),
+        data_type: record.and_then(|r| data_type(r.2)),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    use io::{Error, Result};
+    use property::{Property, PropertyMap};
+    use super::*;
+
+    // Properties of every data type, including a duplicate name.
+    /// Returns properties of every type, with a repeated name.
+    pub fn sample() -> PropertyMap {
+        let mut nested = PropertyMap::new();
+        nested.push("Stage".to_string(), Property::Integer(3));
+        nested.push("Done".to_string(), Property::Boolean(false));
+        vec![
+            ("Name", Property::from("Grimwald")),
+            ("Emeralds", Property::Integer(1234)),
+            ("Str", Property::Float(2.5)),
+            ("Bound", Property::Boolean(true)),
+            ("SkillPoints", Property::List(vec!["0".to_string(), "3".to_string()])),
+            ("Seed", Property::Long(-5)),
+            ("Elapsed", Property::Double(1.25)),
+            ("Blob", Property::Bytes(vec![1, 2, 3])),
+            ("Quest", Property::Record(nested)),
+            ("Odd", Property::Unknown(vec![9, 9], 0x42)),
+            ("Nothing", Property::Empty),
+            ("Name", Property::from("Duplicate")),
+        ].into_iter().map(|(name, prop)| (name.to_string(), prop)).collect()
+    }
+
+    fn encode(props: &PropertyMap) -> Vec<u8> {
+        let mut writer = PropertyWriter::new(Vec::new());
+        writer.write_props(props).unwrap();
+        writer.into_inner()
+    }
+
+    fn decode(data: &[u8]) -> Result<PropertyMap> {
+        PropertyReader::new(data).collect()
+    }
+
+    // Returns the error wrapped in `Error::At`.
+    fn cause(err: Error) -> Error {
+        match err {
+            Error::At(_, err) => *err,
+            err => panic!("expected a location, found {:?}", err),
+        }
+    }
+
+    // Encodes an integer record for "Int" with the given data length, data and end tag.
+    fn int_record(data_len: u32, data: &[u8], end: u8) -> Vec<u8> {
+        let mut buf = vec![0x7e, 3, b'I', b'n', b't'];
+        buf.extend(vec![data_len as u8, 0, 0, 0]);
+        buf.push(TAG_INT);
+        buf.extend(data.iter().cloned());
+        buf.push(end);
+        buf
+    }
+
+    #[test]
+    fn round_trip_every_tag() {
+        let props = sample();
+        let data = encode(&props);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", props));
+        assert_eq!(encode(&decoded), data);
+    }
+
+    #[test]
+    fn data_types() {
+        let props = sample();
+        let tags: Vec<Option<u8>> = props.iter().map(|(_, prop)| data_type(prop)).collect();
+        assert_eq!(tags, vec![
+            Some(TAG_STRING), Some(TAG_INT), Some(TAG_FLOAT), Some(TAG_BOOL), Some(TAG_STRING),
+            Some(TAG_LONG), Some(TAG_DOUBLE), Some(TAG_BYTES), Some(TAG_RECORD), Some(0x42),
+            None, Some(TAG_STRING),
+        ]);
+    }
+
+    #[test]
+    fn inexact_payload_is_unknown() {
+        // A byte array with a trailing byte beyond its length prefix
+        let mut data = vec![0x7e, 1, b'B', 6, 0, 0, 0, TAG_BYTES, 2, 1, 2, 3, 0x7b];
+        let props = decode(&data).unwrap();
+        match props.get("B") {
+            Some(&Property::Unknown(ref v, TAG_BYTES)) => assert_eq!(v, &vec![2, 1, 2, 3]),
+            prop => panic!("expected unknown data, found {:?}", prop),
+        }
+        assert_eq!(encode(&props), data);
+
+        // A record whose nested data cannot be decoded
+        data = vec![0x7e, 1, b'R', 3, 0, 0, 0, TAG_RECORD, 0x00, 0x7b];
+        match decode(&data).unwrap().get("R") {
+            Some(&Property::Unknown(ref v, TAG_RECORD)) => assert_eq!(v, &vec![0x00]),
+            prop => panic!("expected unknown data, found {:?}", prop),
+        }
+    }
+
+    #[test]
+    fn truncated_record() {
+        let data = int_record(6, &[1, 0, 0, 0], 0x7b);
+        for len in 1..data.len() {
+            let mut reader = PropertyReader::new(&data[..len]);
+            match reader.read_property() {
+                Err(err) => match cause(err) {
+                    Error::UnexpectedEOF => {},
+                    err => panic!("truncated at {}: expected end of file, found {:?}", len, err),
+                },
+                res => panic!("truncated at {}: expected an error, found {:?}", len, res),
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_data_len() {
+        let mut data = int_record(7, &[1, 0, 0, 0, 0], 0x7b);
+        data.extend(int_record(6, &[2, 0, 0, 0], 0x7b));
+        let mut reader = PropertyReader::new(&data[..]);
+        match reader.read_property() {
+            Err(err) => match cause(err) {
+                Error::LengthMismatch(7, 6) => {},
+                err => panic!("expected a length mismatch, found {:?}", err),
+            },
+            res => panic!("expected an error, found {:?}", res),
+        }
+        // The declared length frames the record, so the next one can still be read
+        assert!(reader.is_in_sync());
+        match reader.read_property() {
+            Ok(Some((ref name, Property::Integer(2)))) if name == "Int" => {},
+            res => panic!("expected the next record, found {:?}", res),
+        }
+    }
+
+    #[test]
+    fn missing_end_tag() {
+        let data = int_record(6, &[1, 0, 0, 0], 0x00);
+        match decode(&data) {
+            Err(err) => match cause(err) {
+                Error::MissingEndTag(0x00) => {},
+                err => panic!("expected a missing end tag, found {:?}", err),
+            },
+            res => panic!("expected an error, found {:?}", res),
+        }
+    }
+
+    #[test]
+    fn huge_name_length() {
+        // A name length of 4 GiB - 1 must fail on the data at hand, not allocate it
+        let mut data = vec![0x7e, 0xff, 0xff, 0xff, 0xff, 0x0f, b'I', b'n', b't'];
+        match decode(&data) {
+            Err(err) => match cause(err) {
+                Error::UnexpectedEOF => {},
+                err => panic!("expected an unexpected end of file, found {:?}", err),
+            },
+            res => panic!("expected an error, found {:?}", res),
+        }
+        data.extend(int_record(6, &[2, 0, 0, 0], 0x7b));
+        assert_eq!(recover(&data).props.len(), 1);
+    }
+
+    #[test]
+    fn only_damaged_data_is_damage() {
+        let missing = read_path(&env::temp_dir().join("sits-parser-missing.txt")).unwrap_err();
+        assert!(!missing.is_damage());
+        let truncated = decode(&int_record(6, &[1, 0], 0x7b)[..9]).unwrap_err();
+        assert!(truncated.is_damage());
+    }
+
+    #[test]
+    fn recover_resyncs_on_next_record() {
+        let mut data = int_record(6, &[1, 0, 0, 0], 0x7b);
+        data[0] = 0x00;
+        let start = data.len();
+        data.extend(int_record(6, &[2, 0, 0, 0], 0x7b));
+
+        let recovered = recover(&data);
+        assert_eq!(recovered.props.len(), 1);
+        match recovered.props.get("Int") {
+            Some(&Property::Integer(2)) => {},
+            prop => panic!("expected the second record, found {:?}", prop),
+        }
+        assert_eq!(recovered.damaged.len(), 1);
+        assert_eq!(recovered.damaged[0].start, 0);
+        assert_eq!(recovered.damaged[0].end, start as u64);
+    }
+
+    #[test]
+    fn recover_skips_undecodable_record() {
+        let mut data = int_record(7, &[1, 0, 0, 0, 0], 0x7b);
+        let end = data.len();
+        data.extend(int_record(6, &[2, 0, 0, 0], 0x7b));
+
+        let recovered = recover(&data);
+        assert_eq!(recovered.props.len(), 1);
+        assert_eq!(recovered.damaged.len(), 1);
+        assert_eq!(recovered.damaged[0].end, end as u64);
+    }
+
+    #[test]
+    fn verify_reports_first_difference() {
+        let path = env::temp_dir().join("sits-parser-verify.txt");
+
+        File::create(&path).unwrap().write_all(&encode(&sample())).unwrap();
+        assert!(verify_path(&path).unwrap().is_none());
+
+        // A name length in a non-canonical two-byte encoding decodes fine, but is
+        // re-encoded in a single byte.
+        let data = vec![0x7e, 0x83, 0x00, b'I', b'n', b't', 6, 0, 0, 0, TAG_INT, 1, 0, 0, 0, 0x7b];
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let mismatch = verify_path(&path).unwrap().unwrap();
+        assert_eq!(mismatch.offset, 1);
+        assert_eq!(mismatch.name, Some("Int".to_string()));
+        assert_eq!(mismatch.data_type, Some(TAG_INT));
+    }
+}
@@ -0,0 +1,77 @@
+use property::{Property, PropertyMap};
+use skill::{Category, SkillDb};
+
+// The names of the unallocated point properties are unconfirmed: no save game in hand has
+// them. If a name is wrong, its field is disabled and refunds fail, but nothing is added.
+
+/// Property holding the unallocated stat points of a party member.
+pub const STAT_POINTS: &'static str = "StatPoints";
+
+/// Property holding the unallocated skill points of a party member.
+pub const SKILL_POINTS: &'static str = "UnspentSkillPoints";
+
+// Returns the numeric value of a property.
+fn value(prop: Option<&Property>) -> Option<f32> {
+    match prop {
+        Some(&Property::Float(v)) => Some(v),
+        Some(&Property::Integer(v)) => Some(v as f32),
+        _ => None,
+    }
+}
+
+// Sets the numeric value of an existing property, keeping its type; missing properties are
+// never added, as the game may not use them.
+fn set_value(member: &mut PropertyMap, key: &str, v: f32) -> Result<(), String> {
+    let prop = match member.get(key) {
+        Some(prop) => try!(prop.parse_like(&v.to_string())
+            .ok_or(format!("Invalid value {} for {}.", v, key))),
+        None => return Err(format!("Missing {}.", key)),
+    };
+    member.insert(key.to_string(), prop);
+    Ok(())
+}
+
+// Returns whether the points spent on a skill can be refunded: those of combat and spell
+// skills can, while aptitudes and reserved slots are left alone.
+fn is_refundable(skills: &SkillDb, index: usize) -> bool {
+    match skills.get(index) {
+        Some(skill) => skill.category != Category::Aptitude,
+        None => false,
+    }
+}
+
+/// Returns the skill points spent by a party member on combat and spell skills.
+pub fn spent_skill_points(member: &PropertyMap, skills: &SkillDb) -> Result<f32, String> {
+    match member.get("SkillPoints") {
+        Some(&Property::List(ref v)) => {
+            let mut total = 0.0;
+            for (i, s) in v.iter().enumerate().filter(|&(i, _)| is_refundable(skills, i)) {
+                total += try!(s.parse::<f32>()
+                    .map_err(|_| format!("Invalid skill points {:?} at index {}.", s, i)));
+            }
+            Ok(total)
+        },
+        _ => Err("Missing skill points.".to_string()),
+    }
+}
+
+/// Returns the skill points spent by a party member on combat and spell skills to the
+/// unallocated pool, and the number of points refunded.
+///
+/// Every combat and spell skill is reset to zero; aptitudes are kept. The member is left
+/// unchanged if any of these skills is not a number, or if it has no unallocated skill
+/// points.
+pub fn refund_skill_points(member: &mut PropertyMap, skills: &SkillDb) -> Result<f32, String> {
+    let spent = try!(spent_skill_points(member, skills));
+    let pool = try!(value(member.get(SKILL_POINTS))
+        .ok_or(format!("Missing unallocated skill points, {}.", SKILL_POINTS)));
+    try!(set_value(member, SKILL_POINTS, pool + spent));
+    if let Some(&mut Property::List(ref mut v)) = member.get_mut("SkillPoints") {
+        for (i, s) in v.iter_mut().enumerate() {
+            if is_refundable(skills, i) {
+                *s = "0".to_string();
+            }
+        }
+    }
+    Ok(spent)
+}
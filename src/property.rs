@@ -1,16 +1,50 @@
-use std::collections::HashMap;
 use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
 
 #[derive(Debug)]
 pub enum Property {
+    Empty,
     Boolean(bool),
     Integer(u32),
+    /// Speculative; not yet seen in a save game.
+    Long(i64),
     Float(f32),
+    /// Speculative; not yet seen in a save game.
+    Double(f64),
     String(String),
     List(Vec<String>),
+    /// Speculative; not yet seen in a save game.
+    Bytes(Vec<u8>),
+    /// Speculative; not yet seen in a save game.
+    Record(PropertyMap),
+    /// Data of an unrecognized type, with its type tag.
     Unknown(Vec<u8>, u8),
 }
 
+impl Property {
+    /// Parses a string as a value of the same type as this property.
+    ///
+    /// Returns `None` if the string is not a valid value of that type, or if values of that
+    /// type cannot be represented as a string.
+    pub fn parse_like(&self, s: &str) -> Option<Property> {
+        match *self {
+            Property::Boolean(..) => s.parse::<bool>().ok().map(Property::Boolean),
+            Property::Integer(..) => s.parse::<u32>().ok().map(Property::Integer),
+            Property::Long(..) => s.parse::<i64>().ok().map(Property::Long),
+            Property::Float(..) => s.parse::<f32>().ok().map(Property::Float),
+            Property::Double(..) => s.parse::<f64>().ok().map(Property::Double),
+            Property::String(..) => Some(Property::from(s)),
+            Property::List(..) => Some(Property::List(s.split(",").map(String::from).collect())),
+            Property::Empty |
+            Property::Bytes(..) |
+            Property::Record(..) |
+            Property::Unknown(..) => None,
+        }
+    }
+}
+
 impl From<bool> for Property {
     fn from(v: bool) -> Self { Property::Boolean(v) }
 }
@@ -19,10 +53,18 @@ impl From<u32> for Property {
     fn from(v: u32) -> Self { Property::Integer(v) }
 }
 
+impl From<i64> for Property {
+    fn from(v: i64) -> Self { Property::Long(v) }
+}
+
 impl From<f32> for Property {
     fn from(v: f32) -> Self { Property::Float(v) }
 }
 
+impl From<f64> for Property {
+    fn from(v: f64) -> Self { Property::Double(v) }
+}
+
 impl From<String> for Property {
     fn from(v: String) -> Self { Property::String(v) }
 }
@@ -35,12 +77,23 @@ impl<'a> From<&'a str> for Property {
     fn from(v: &'a str) -> Self { Property::String(String::from(v)) }
 }
 
+impl From<Vec<u8>> for Property {
+    fn from(v: Vec<u8>) -> Self { Property::Bytes(v) }
+}
+
+impl From<PropertyMap> for Property {
+    fn from(v: PropertyMap) -> Self { Property::Record(v) }
+}
+
 impl fmt::Display for Property {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Property::Empty => Ok(()),
             Property::Boolean(v) => write!(f, "{}", v),
             Property::Integer(v) => write!(f, "{}", v),
+            Property::Long(v) => write!(f, "{}", v),
             Property::Float(v) => write!(f, "{}", v),
+            Property::Double(v) => write!(f, "{}", v),
             Property::String(ref v) => write!(f, "{}", v),
             Property::List(ref v) => {
                 let joined: String = v.iter().fold("".to_string(), |mut i, j| {
@@ -52,10 +105,92 @@ impl fmt::Display for Property {
                 });
                 write!(f, "{}", joined)
             },
+            Property::Bytes(ref v) => write!(f, "<{} bytes>", v.len()),
+            Property::Record(ref v) => write!(f, "<{} properties>", v.len()),
             Property::Unknown(ref v, _) => write!(f, "<{} bytes>", v.len()),
         }
     }
 }
 
-/// A collection of properties.
-pub type PropertyMap = HashMap<String, Property>;
+/// An ordered collection of properties.
+///
+/// Entries are kept in the order they were read, including any duplicate names, so that
+/// writing back an unmodified collection reproduces the original file exactly.
+#[derive(Debug, Default)]
+pub struct PropertyMap {
+    entries: Vec<(String, Property)>,
+}
+
+impl PropertyMap {
+    pub fn new() -> Self {
+        PropertyMap { entries: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns a reference to the first property with the given name.
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.entries.iter().find(|e| e.0 == name).map(|e| &e.1)
+    }
+
+    /// Returns a mutable reference to the first property with the given name.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Property> {
+        self.entries.iter_mut().find(|e| e.0 == name).map(|e| &mut e.1)
+    }
+
+    /// Replaces the value of the first property with the given name, keeping its position;
+    /// if there is no such property, it is appended. Returns the previous value, if any.
+    pub fn insert(&mut self, name: String, prop: Property) -> Option<Property> {
+        match self.entries.iter().position(|e| e.0 == name) {
+            Some(i) => Some(mem::replace(&mut self.entries[i].1, prop)),
+            None => {
+                self.entries.push((name, prop));
+                None
+            }
+        }
+    }
+
+    /// Appends a property, even if one with the same name already exists.
+    pub fn push(&mut self, name: String, prop: Property) {
+        self.entries.push((name, prop));
+    }
+
+    /// Iterates over the properties in order.
+    pub fn iter<'a>(&'a self) -> Iter<'a> {
+        Iter { inner: self.entries.iter() }
+    }
+}
+
+/// Iterator over the entries of a `PropertyMap`, in order.
+pub struct Iter<'a> {
+    inner: slice::Iter<'a, (String, Property)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a String, &'a Property);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|e| (&e.0, &e.1))
+    }
+}
+
+impl FromIterator<(String, Property)> for PropertyMap {
+    fn from_iter<I: IntoIterator<Item=(String, Property)>>(iter: I) -> Self {
+        PropertyMap { entries: iter.into_iter().collect() }
+    }
+}
+
+impl<'a> IntoIterator for &'a PropertyMap {
+    type Item = (&'a String, &'a Property);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
@@ -0,0 +1,44 @@
+use property::{Property, PropertyMap};
+
+/// Stats of a party member, stored as modifiers from their base value of 10.
+pub static STATS: [&'static str; 5] = ["Int", "Dex", "Str", "Occ", "Per"];
+
+/// Range of valid stat values, which are stored as modifiers from their base value of 10.
+pub const STAT_MIN: f32 = -9.0;
+pub const STAT_MAX: f32 = 99.0;
+
+/// Returns the race of a party member.
+pub fn race_of(member: &PropertyMap) -> Option<&str> {
+    match member.get("Race") {
+        Some(&Property::String(ref name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the races of the given party members, in order and without duplicates.
+///
+/// The races of the game are not known to the editor, only those found in save games.
+pub fn races<'a, I>(members: I) -> Vec<String>
+    where I: IntoIterator<Item = &'a PropertyMap> {
+
+    let mut races: Vec<String> = members.into_iter()
+        .filter_map(|member| race_of(member).map(String::from))
+        .collect();
+    races.sort();
+    races.dedup();
+    races
+}
+
+/// Changes the race of a party member.
+///
+/// The racial modifiers of each race are not known, so stats are left as they are; they
+/// include the racial modifiers of the previous race until adjusted.
+pub fn set_race(member: &mut PropertyMap, race: &str) -> Result<(), String> {
+    match member.get_mut("Race") {
+        Some(&mut Property::String(ref mut v)) => {
+            *v = race.to_string();
+            Ok(())
+        },
+        _ => Err("Missing race.".to_string()),
+    }
+}
@@ -0,0 +1,139 @@
+use property::{Property, PropertyMap};
+
+/// Id of an empty slot in the party.
+pub const EMPTY_SLOT: &'static str = "0";
+
+/// Returns the party slots listed in the game file, including empty slots.
+pub fn slots(game: &PropertyMap) -> Vec<String> {
+    match game.get("PartyIDs") {
+        Some(&Property::String(ref ids)) if !ids.is_empty() => {
+            ids.split(",").map(String::from).collect()
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the ids of the party members listed in the game file, skipping empty slots.
+pub fn party_ids(game: &PropertyMap) -> Vec<String> {
+    slots(game).into_iter().filter(|id| id != EMPTY_SLOT).collect()
+}
+
+// Writes back the party slots, comma-separated like the game writes them.
+fn set_slots(game: &mut PropertyMap, slots: &[String]) {
+    game.insert("PartyIDs".to_string(), Property::from(slots.join(",")));
+}
+
+/// Returns the id of a companion.
+pub fn id_of(member: &PropertyMap) -> Option<&str> {
+    match member.get("PartyID") {
+        Some(&Property::String(ref id)) => Some(id),
+        _ => None,
+    }
+}
+
+/// Returns the party slot of a companion, or `None` if it is benched.
+pub fn slot_of(game: &PropertyMap, id: &str) -> Option<usize> {
+    slots(game).iter().position(|v| v == id)
+}
+
+/// Returns whether a companion is in the party, rather than benched.
+pub fn in_party(game: &PropertyMap, member: &PropertyMap) -> bool {
+    id_of(member).and_then(|id| slot_of(game, id)).is_some()
+}
+
+/// Returns a key to sort companions by: party members in order of their slot, then those
+/// benched in order of id.
+pub fn sort_key(game: &PropertyMap, member: &PropertyMap) -> (usize, u64) {
+    let id = id_of(member).unwrap_or(EMPTY_SLOT);
+    (slot_of(game, id).unwrap_or(usize::max_value()), id.parse::<u64>().unwrap_or(0))
+}
+
+// Swaps the slot of a party member with the one above or below it.
+fn shift(game: &mut PropertyMap, id: &str, up: bool) -> Result<(), String> {
+    let mut slots = slots(game);
+    let i = try!(slots.iter().position(|v| v == id)
+        .ok_or(format!("Companion {} is not in the party.", id)));
+    let j = if up { i.checked_sub(1) } else { Some(i + 1) };
+    match j {
+        Some(j) if j < slots.len() => {
+            slots.swap(i, j);
+            set_slots(game, &slots);
+            Ok(())
+        },
+        _ => Err(format!("Companion {} cannot be moved {}.", id, if up { "up" } else { "down" })),
+    }
+}
+
+/// Moves a party member up by one slot.
+pub fn move_up(game: &mut PropertyMap, id: &str) -> Result<(), String> {
+    shift(game, id, true)
+}
+
+/// Moves a party member down by one slot.
+pub fn move_down(game: &mut PropertyMap, id: &str) -> Result<(), String> {
+    shift(game, id, false)
+}
+
+/// Moves a benched companion into the first empty slot of the party.
+pub fn add(game: &mut PropertyMap, id: &str) -> Result<(), String> {
+    let mut slots = slots(game);
+    if id == EMPTY_SLOT || slots.iter().any(|v| v == id) {
+        return Err(format!("Companion {} is already in the party.", id));
+    }
+    let i = try!(slots.iter().position(|v| v == EMPTY_SLOT)
+        .ok_or("The party is full.".to_string()));
+    slots[i] = id.to_string();
+    set_slots(game, &slots);
+    Ok(())
+}
+
+/// Takes a companion out of the party, leaving its slot empty.
+pub fn remove(game: &mut PropertyMap, id: &str) -> Result<(), String> {
+    let mut slots = slots(game);
+    let i = try!(slots.iter().position(|v| v == id)
+        .ok_or(format!("Companion {} is not in the party.", id)));
+    if slots.iter().filter(|&v| v != EMPTY_SLOT).count() == 1 {
+        return Err("The party cannot be empty.".to_string());
+    }
+    slots[i] = EMPTY_SLOT.to_string();
+    set_slots(game, &slots);
+    Ok(())
+}
+
+/// Checks that the party listed in the game file is consistent with the companion files.
+///
+/// The game file must list the party, every party member must be listed once and have a
+/// companion file, and every companion must have a distinct id. Returns a description of
+/// each problem found.
+pub fn check<'a, I>(game: &PropertyMap, members: I) -> Vec<String>
+    where I: IntoIterator<Item = &'a PropertyMap> {
+
+    let mut errors: Vec<String> = Vec::new();
+    match game.get("PartyIDs") {
+        Some(&Property::String(..)) => {},
+        _ => errors.push("The game file is missing its PartyIDs.".to_string()),
+    }
+    let mut ids: Vec<&str> = Vec::new();
+    for member in members {
+        match id_of(member) {
+            Some(id) if ids.contains(&id) => {
+                errors.push(format!("Companion {} is stored more than once.", id))
+            },
+            Some(id) => ids.push(id),
+            None => errors.push("A companion is missing its PartyID.".to_string()),
+        }
+    }
+
+    let slots = slots(game);
+    for (i, id) in slots.iter().enumerate() {
+        if id == EMPTY_SLOT {
+            continue;
+        }
+        if slots[..i].contains(id) {
+            errors.push(format!("Companion {} is in the party more than once.", id));
+        } else if !ids.contains(&&id[..]) {
+            errors.push(format!("Companion {} is in the party, but has no companion file.", id));
+        }
+    }
+    errors
+}
@@ -0,0 +1,348 @@
+use property::{Property, PropertyMap};
+use race::{self, STAT_MAX, STAT_MIN};
+use roster;
+use skill::{Category, SkillDb};
+
+/// Number of combat selects and spell favorites.
+pub const COMBAT_SELECTS: usize = 3;
+pub const SPELL_FAVORITES: usize = 10;
+
+/// Weapon proficiencies of a party member.
+pub static WEAPONS: [&'static str; 8] = [
+    "WpnSword", "WpnShortSword", "WpnSceptor", "WpnAxe", "WpnWhip", "WpnBow", "WpnXbow", "WpnElixir",
+];
+
+/// Highest weapon proficiency.
+pub const WEAPON_MAX: f32 = 650.0;
+
+/// Sum of two stats, including their base values, needed for each grade from 1 to 3.
+pub static GRADE_THRESHOLDS: [f32; 3] = [21.0, 26.0, 32.0];
+
+/// Returns the sum of two stats, including their base values of 10.
+pub fn grade_total(member: &PropertyMap, first: &str, second: &str) -> Option<f32> {
+    match (member.get(first), member.get(second)) {
+        (Some(&Property::Float(first_mod)), Some(&Property::Float(second_mod))) => {
+            Some(first_mod + second_mod + 20.0)
+        },
+        _ => None,
+    }
+}
+
+/// Returns the grade from 0 to 3 reached by the sum of two stats.
+pub fn grade(member: &PropertyMap, first: &str, second: &str) -> f32 {
+    match grade_total(member, first, second) {
+        Some(total) => GRADE_THRESHOLDS.iter().filter(|&&t| total >= t).count() as f32,
+        None => 0.0,
+    }
+}
+
+/// Returns the combat grade of a party member, from Str and Dex.
+pub fn combat_grade(member: &PropertyMap) -> f32 {
+    grade(member, "Str", "Dex")
+}
+
+/// Returns the spell grade of a party member, from Int and Occ.
+pub fn spell_grade(member: &PropertyMap) -> f32 {
+    grade(member, "Int", "Occ")
+}
+
+/// Returns the spell grade needed to learn a spell skill, by its index, if known.
+pub fn spell_tier(skills: &SkillDb, index: usize) -> Option<f32> {
+    match skills.get(index) {
+        Some(skill) if skill.category == Category::Spell => skill.tier.map(|t| t as f32),
+        _ => None,
+    }
+}
+
+/// Returns the internal names of the combat and spell skills known by a party member.
+///
+/// Combat skills are known once any points are spent on them; spell skills are also
+/// known once the spell grade unlocks their tier.
+pub fn known_skills(member: &PropertyMap, skills: &SkillDb) -> (Vec<String>, Vec<String>) {
+    let spell_grade = spell_grade(member);
+    let mut combat_skills: Vec<String> = Vec::new();
+    let mut spell_skills: Vec<String> = Vec::new();
+
+    if let Some(&Property::List(ref v)) = member.get("SkillPoints") {
+        for (i, ref val) in v.iter().enumerate() {
+            let skill = match skills.get(i) {
+                Some(skill) => skill,
+                None => continue,
+            };
+            let unlocked = spell_tier(skills, i).map_or(false, |tier| spell_grade >= tier);
+            match (skill.category, val.parse::<u32>()) {
+                (Category::Combat, Ok(n)) if n > 0 => combat_skills.push(skill.internal.to_string()),
+                (Category::Spell, Ok(n)) if n > 0 || unlocked => {
+                    spell_skills.push(skill.internal.to_string())
+                },
+                _ => {}
+            }
+        }
+    }
+    (combat_skills, spell_skills)
+}
+
+/// Applies the consistency rules of the game to a party member.
+///
+/// The combat and spell grades, and the lists of known combat and spell skills, are
+/// recomputed from stats and skill points. Skills that are no longer known are cleared
+/// from their slots of the combat selects and spell favorites, keeping the other skills in
+/// place, and both are padded to their full size.
+pub fn normalize(member: &mut PropertyMap, skills: &SkillDb) {
+    let combat_grade = combat_grade(member);
+    let spell_grade = spell_grade(member);
+    let (combat_skills, spell_skills) = known_skills(member, skills);
+
+    if let Some(&mut Property::List(ref mut v)) = member.get_mut("CombatSelects") {
+        for x in v.iter_mut() {
+            if !combat_skills.contains(x) {
+                *x = "Empty".to_string();
+            }
+        }
+        while v.len() < COMBAT_SELECTS {
+            v.push("Empty".to_string());
+        }
+    }
+    if let Some(&mut Property::List(ref mut v)) = member.get_mut("SpellFavorites") {
+        for x in v.iter_mut() {
+            if !spell_skills.contains(x) {
+                *x = "".to_string();
+            }
+        }
+        while v.len() < SPELL_FAVORITES {
+            v.push("".to_string());
+        }
+    }
+    member.insert("CombatGrade".to_string(), Property::Float(combat_grade));
+    member.insert("CombatSkills".to_string(), Property::List(combat_skills));
+    member.insert("SpellGrade".to_string(), Property::Float(spell_grade));
+    member.insert("SpellSkills".to_string(), Property::List(spell_skills));
+}
+
+/// Returns the points spent on a skill, by its index.
+pub fn skill_points(member: &PropertyMap, index: usize) -> u32 {
+    match member.get("SkillPoints") {
+        Some(&Property::List(ref v)) => v.get(index).and_then(|s| s.parse::<u32>().ok()).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Problems found in a save game by `validate`.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Problems that would leave the save game unusable; it must not be saved.
+    pub errors: Vec<String>,
+    /// Values the game would not normally produce; saving is possible, but unwise.
+    pub warnings: Vec<String>,
+}
+
+impl Report {
+    /// Returns whether no problems were found.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty() && self.warnings.is_empty()
+    }
+}
+
+// Returns how a party member is referred to in a report.
+fn describe(member: &PropertyMap) -> String {
+    match (member.get("Name"), roster::id_of(member)) {
+        (Some(&Property::String(ref name)), _) => name.to_string(),
+        (_, Some(id)) => format!("Companion {}", id),
+        _ => "Unknown companion".to_string(),
+    }
+}
+
+// Returns the numeric value of a property.
+fn value(member: &PropertyMap, key: &str) -> Option<f32> {
+    match member.get(key) {
+        Some(&Property::Float(v)) => Some(v),
+        Some(&Property::Integer(v)) => Some(v as f32),
+        _ => None,
+    }
+}
+
+// Validates a party member, adding problems found to the report.
+fn validate_member(member: &PropertyMap, skills: &SkillDb, report: &mut Report) {
+    let name = describe(member);
+    let spell_grade = spell_grade(member);
+    let slots = skills.slots();
+    match member.get("SkillPoints") {
+        Some(&Property::List(ref v)) if v.len() <= slots => {
+            for (i, s) in v.iter().enumerate() {
+                let n = match s.parse::<u32>() {
+                    Ok(n) => n,
+                    Err(_) => {
+                        report.errors.push(format!("{}: invalid skill points {:?} at index {}.", name, s, i));
+                        continue;
+                    },
+                };
+                let skill = skills.get(i).map(|s| s.name.clone()).unwrap_or(format!("skill {}", i));
+                match spell_tier(skills, i) {
+                    Some(tier) if n > 0 && tier > spell_grade => {
+                        report.warnings.push(format!("{}: {} needs spell grade {}, not {}.",
+                                                     name, skill, tier, spell_grade));
+                    },
+                    _ => {}
+                }
+            }
+        },
+        Some(&Property::List(ref v)) => {
+            report.errors.push(format!("{}: {} skill points entries, more than {}.",
+                                       name, v.len(), slots));
+        },
+        _ => report.errors.push(format!("{}: missing or malformed SkillPoints.", name)),
+    }
+
+    for &key in race::STATS.iter() {
+        if let Some(v) = value(member, key) {
+            if v < STAT_MIN || v > STAT_MAX {
+                report.warnings.push(format!("{}: {} is {}, outside of the range {} to {}.",
+                                             name, key, v, STAT_MIN, STAT_MAX));
+            }
+        }
+    }
+
+    if let (Some(cur), Some(max)) = (value(member, "CurrHealth"), value(member, "MaxHealth")) {
+        if cur > max {
+            report.warnings.push(format!("{}: HP {} is above maximum {}.", name, cur, max));
+        }
+    }
+
+    for &key in WEAPONS.iter() {
+        if let Some(v) = value(member, key) {
+            if v > WEAPON_MAX {
+                report.warnings.push(format!("{}: {} is {}, above {}.", name, key, v, WEAPON_MAX));
+            }
+        }
+    }
+}
+
+/// Validates the game and all companions before saving.
+pub fn validate(game: &PropertyMap, members: &[&PropertyMap], skills: &SkillDb) -> Report {
+    let mut report = Report::default();
+    report.errors.extend(roster::check(game, members.iter().map(|&m| m)));
+    for member in members.iter() {
+        validate_member(member, skills, &mut report);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use property::{Property, PropertyMap};
+    use skill::SkillDb;
+    use super::*;
+
+    // Returns an aptitude at 1, a combat skill at 2, and a spell of each tier at 3 to 5.
+    fn skills() -> SkillDb {
+        SkillDb::parse(r#"<skills>
+            <skill number="1" spritename="Apt"><name>Apt</name><description>a</description></skill>
+            <skill number="2" spritename="Slash"><name>Slash</name><wpntype>Any Weapon</wpntype>
+                <description>b</description></skill>
+            <skill number="3" spritename="One" tier="1"><name>One</name>
+                <description>@Forging School@ c</description></skill>
+            <skill number="4" spritename="Two" tier="2"><name>Two</name>
+                <description>@Forging School@ d</description></skill>
+            <skill number="5" spritename="Three" tier="3"><name>Three</name>
+                <description>@Forging School@ e</description></skill>
+        </skills>"#).unwrap()
+    }
+
+    // Returns a companion with the given spell grade stats and skill points.
+    fn member(int: f32, occ: f32, points: &[&str]) -> PropertyMap {
+        let mut member = PropertyMap::new();
+        member.push("PartyID".to_string(), Property::from("1"));
+        member.push("Int".to_string(), Property::Float(int));
+        member.push("Occ".to_string(), Property::Float(occ));
+        member.push("SkillPoints".to_string(),
+                    Property::List(points.iter().map(|&s| s.to_string()).collect()));
+        member
+    }
+
+    fn list(member: &PropertyMap, key: &str) -> Vec<String> {
+        match member.get(key) {
+            Some(&Property::List(ref v)) => v.clone(),
+            prop => panic!("expected a list for {}, found {:?}", key, prop),
+        }
+    }
+
+    #[test]
+    fn grade_thresholds() {
+        // Stats are stored as modifiers to a base value of 10
+        for &(total, expected) in [(20.0, 0.0), (21.0, 1.0), (25.5, 1.0), (26.0, 2.0),
+                                    (31.0, 2.0), (32.0, 3.0), (60.0, 3.0)].iter() {
+            let member = member(total - 20.0, 0.0, &[]);
+            assert_eq!(spell_grade(&member), expected, "total {}", total);
+        }
+        assert_eq!(combat_grade(&member(20.0, 20.0, &[])), 0.0);
+    }
+
+    #[test]
+    fn spell_tiers_gate_spells() {
+        let skills = skills();
+        assert_eq!(spell_tier(&skills, 2), None);
+        assert_eq!(spell_tier(&skills, 4), Some(2.0));
+
+        // Grade 1 unlocks the first tier only; the others are known with points
+        let (combat, spells) = known_skills(&member(1.0, 0.0, &["0", "3", "0", "0", "0", "0"]), &skills);
+        assert!(combat.is_empty());
+        assert_eq!(spells, vec!["One"]);
+        let (combat, spells) = known_skills(&member(1.0, 0.0, &["0", "3", "1", "0", "0", "2"]), &skills);
+        assert_eq!(combat, vec!["Slash"]);
+        assert_eq!(spells, vec!["One", "Three"]);
+        let (_, spells) = known_skills(&member(12.0, 0.0, &["0", "0", "0", "0", "0", "0"]), &skills);
+        assert_eq!(spells, vec!["One", "Two", "Three"]);
+    }
+
+    #[test]
+    fn normalize_clears_unknown_slots_in_place() {
+        let skills = skills();
+        let mut member = member(1.0, 0.0, &["0", "0", "1", "0", "0", "0"]);
+        member.push("CombatSelects".to_string(),
+                    Property::List(vec!["Bogus".to_string(), "Slash".to_string()]));
+        member.push("SpellFavorites".to_string(),
+                    Property::List(vec!["Two".to_string(), "".to_string(), "One".to_string()]));
+        normalize(&mut member, &skills);
+
+        assert_eq!(list(&member, "CombatSelects"), vec!["Empty", "Slash", "Empty"]);
+        let mut favorites = vec![String::new(); SPELL_FAVORITES];
+        favorites[2] = "One".to_string();
+        assert_eq!(list(&member, "SpellFavorites"), favorites);
+        assert_eq!(list(&member, "CombatSkills"), vec!["Slash"]);
+        assert_eq!(list(&member, "SpellSkills"), vec!["One"]);
+        match member.get("SpellGrade") {
+            Some(&Property::Float(grade)) => assert_eq!(grade, 1.0),
+            prop => panic!("expected the spell grade, found {:?}", prop),
+        }
+    }
+
+    #[test]
+    fn validate_splits_errors_and_warnings() {
+        let skills = skills();
+        let mut game = PropertyMap::new();
+        game.push("PartyIDs".to_string(), Property::from("1,0"));
+
+        // Values the game would not produce are warnings, and can be saved
+        let mut member = member(1.0, 0.0, &["0", "0", "0", "0", "1", "0"]);
+        member.push("CurrHealth".to_string(), Property::Float(20.0));
+        member.push("MaxHealth".to_string(), Property::Float(10.0));
+        let report = validate(&game, &[&member], &skills);
+        assert!(report.errors.is_empty(), "{:?}", report);
+        assert_eq!(report.warnings.len(), 2, "{:?}", report);
+
+        // Values the game could not load are errors
+        member.insert("SkillPoints".to_string(), Property::List(vec!["x".to_string()]));
+        let report = validate(&PropertyMap::new(), &[&member], &skills);
+        assert_eq!(report.errors.len(), 2, "{:?}", report);
+    }
+
+    #[test]
+    fn validate_reports_missing_id_once() {
+        let mut game = PropertyMap::new();
+        game.push("PartyIDs".to_string(), Property::from("0"));
+        let mut member = PropertyMap::new();
+        member.push("SkillPoints".to_string(), Property::List(vec!["0".to_string()]));
+        let report = validate(&game, &[&member], &skills());
+        assert_eq!(report.errors, vec!["A companion is missing its PartyID.".to_string()]);
+    }
+}
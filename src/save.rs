@@ -0,0 +1,81 @@
+use std::fs::{copy, read_dir};
+use std::path::{Path, PathBuf};
+
+use time;
+
+use parser::{verify_path, write_path};
+use property::PropertyMap;
+use roster;
+
+/// Returns the path of the game file in a save game directory.
+pub fn game_path(dir: &Path) -> PathBuf {
+    dir.join("Game.txt")
+}
+
+/// Returns the path of a party member file in a save game directory.
+pub fn party_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join("Party".to_string() + id + ".txt")
+}
+
+// Returns the id of a party member file name, "Party<id>.txt"; backups, which have a
+// timestamp before the extension, are not matched.
+fn party_file_id(file_name: &str) -> Option<&str> {
+    if file_name.starts_with("Party") && file_name.ends_with(".txt") {
+        let id = &file_name["Party".len()..file_name.len() - ".txt".len()];
+        if !id.is_empty() && id.chars().all(|c| c.is_digit(10)) {
+            return Some(id);
+        }
+    }
+    None
+}
+
+/// Returns the ids of all companions in a save game directory: first the party members
+/// listed in the game file, in order, then those benched, in order of id.
+pub fn companion_ids(dir: &Path, game: &PropertyMap) -> Result<Vec<String>, String> {
+    let mut ids = roster::party_ids(game);
+    let mut benched: Vec<String> = Vec::new();
+    let entries = try!(read_dir(dir).map_err(|e| format!("Cannot read {:?}: {}", dir, e)));
+    for entry in entries {
+        let entry = try!(entry.map_err(|e| format!("Cannot read {:?}: {}", dir, e)));
+        if let Some(id) = entry.file_name().to_str().and_then(party_file_id) {
+            if !ids.iter().any(|v| v == id) {
+                benched.push(id.to_string());
+            }
+        }
+    }
+    benched.sort_by_key(|id| id.parse::<u64>().unwrap_or(0));
+    ids.extend(benched);
+    Ok(ids)
+}
+
+/// Returns a timestamp suitable for labeling backups, including the file extension.
+pub fn timestamp() -> String {
+    time::strftime("%FT%H.%M.%SZ.txt", &time::now_utc()).unwrap()
+}
+
+/// Returns the path of a timestamped backup of the given file.
+pub fn backup_path(path: &Path, timestamp: &str) -> PathBuf {
+    path.with_extension(timestamp)
+}
+
+/// Copies a file to its timestamped backup.
+pub fn backup(path: &Path, timestamp: &str) -> Result<PathBuf, String> {
+    let dest = backup_path(path, timestamp);
+    try!(copy(path, &dest).map_err(|e| format!("Cannot back up {:?}: {}", path, e)));
+    Ok(dest)
+}
+
+/// Checks that a file survives a round-trip unchanged, so that it can be safely overwritten.
+pub fn verify(path: &Path) -> Result<(), String> {
+    match verify_path(path) {
+        Ok(None) => Ok(()),
+        Ok(Some(mismatch)) => Err(format!("Cannot verify {:?}: {}", path, mismatch)),
+        Err(e) => Err(format!("Cannot verify {:?}: {}", path, e)),
+    }
+}
+
+/// Backs up a file, then overwrites it with the given properties.
+pub fn write_with_backup(path: &Path, props: &PropertyMap, timestamp: &str) -> Result<(), String> {
+    try!(backup(path, timestamp));
+    write_path(path, props).map_err(|e| format!("Cannot write {:?}: {}", path, e))
+}
@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use xml;
+
+/// Kind of a skill, as told by the game data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    /// Used outside of combat, such as Herbology.
+    Aptitude,
+    /// Used in combat: it has a weapon type or levels, and no school of magic.
+    Combat,
+    /// Spell of a school of magic, named at the start of its description.
+    Spell,
+}
+
+/// An aptitude, skill or spell.
+pub struct Skill {
+    /// Name shown in the game.
+    pub name: String,
+    /// Name the game uses to refer to the skill in save games.
+    pub internal: String,
+    /// Aptitude, combat skill or spell.
+    pub category: Category,
+    /// Spell grade needed to learn a spell, if known.
+    pub tier: Option<u32>,
+    /// Weapons needed to use the skill, as described in the game data.
+    pub weapon_type: Option<String>,
+    /// Description, in paragraphs separated by blank lines.
+    pub description: String,
+    /// Level of the first effects described: 0 for spells, 1 for other skills.
+    pub first_level: u32,
+    /// Effects at each level, starting at the first level.
+    pub levels: Vec<String>,
+}
+
+impl Skill {
+    /// Returns the effects of the skill at a level, if any are described.
+    pub fn level_text(&self, level: u32) -> Option<&str> {
+        if level < self.first_level {
+            return None;
+        }
+        match self.levels.get((level - self.first_level) as usize) {
+            Some(text) if !text.is_empty() => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Returns the highest level with described effects, if any.
+    pub fn max_level(&self) -> Option<u32> {
+        match self.levels.len() {
+            0 => None,
+            n => Some(self.first_level + n as u32 - 1),
+        }
+    }
+}
+
+// Highest number of levels described for a skill, in elements skill0 to skill16.
+const MAX_LEVELS: usize = 17;
+
+// Returns whether a description from the game data starts with a school of magic, such as
+// "@Herbology School@".
+fn has_school(content: &str) -> bool {
+    let mut parts = content.trim().split('@');
+    parts.next() == Some("") && parts.next().map_or(false, |s| s.trim().ends_with(" School"))
+}
+
+// Returns the level in the heading of the effects at a level, such as "Skill Level 0:".
+fn parse_level(content: &str) -> Option<u32> {
+    let content = content.trim();
+    if !content.starts_with("Skill Level ") {
+        return None;
+    }
+    content["Skill Level ".len()..].split(':').nth(0).and_then(|s| s.trim().parse::<u32>().ok())
+}
+
+// Formats text from the game data: runs of `@` separate paragraphs, and blank lines and
+// indentation within paragraphs are removed.
+fn format_text(content: &str) -> String {
+    content.split('@')
+        .map(|p| {
+            p.lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .collect::<Vec<&str>>()
+                .join("\n")
+        })
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+/// Name of the skills file in the game data.
+pub const SKILLS_FILE: &'static str = "skills.xml";
+
+// Deepest subdirectory searched for a skills file, counting from the directory given.
+const MAX_SEARCH_DEPTH: usize = 6;
+
+// Returns whether a path is a symbolic link, which the search for a skills file does not
+// follow into, lest it loop.
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|m| m.file_type().is_symlink()).unwrap_or(true)
+}
+
+// Returns the path of the skills file in a directory, such as the game install, searching
+// its subdirectories in order, down to the given depth.
+fn find_skills_file(dir: &Path, depth: usize) -> Option<PathBuf> {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return None,
+    };
+    entries.sort();
+    for path in entries.iter() {
+        match path.file_name().and_then(|s| s.to_str()) {
+            Some(name) if name.to_lowercase() == SKILLS_FILE && path.is_file() => {
+                return Some(path.clone())
+            },
+            _ => {}
+        }
+    }
+    if depth == 0 {
+        return None;
+    }
+    entries.iter()
+        .filter(|path| path.is_dir() && !is_symlink(path))
+        .filter_map(|path| find_skills_file(path, depth - 1))
+        .nth(0)
+}
+
+/// Skills by their index in the `SkillPoints` list of a party member.
+pub struct SkillDb {
+    skills: BTreeMap<usize, Skill>,
+}
+
+impl SkillDb {
+    /// Returns the skills built into the editor.
+    pub fn builtin() -> SkillDb {
+        SkillDb::parse(include_str!("../resources/skills.xml")).unwrap()
+    }
+
+    /// Returns the skills read from a skills file, or the built-in skills if none is given.
+    ///
+    /// The path may also be a directory, such as the game install, which is searched for a
+    /// skills file. The skills file replaces the built-in skills entirely; as the game data
+    /// does not give the tiers of spells, they are taken from the built-in spell with the same
+    /// internal name.
+    pub fn load(path: Option<&Path>) -> Result<SkillDb, String> {
+        let builtin = SkillDb::builtin();
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(builtin),
+        };
+        let file = if path.is_dir() {
+            try!(find_skills_file(path, MAX_SEARCH_DEPTH)
+                .ok_or(format!("{}: no {} found.", path.display(), SKILLS_FILE)))
+        } else {
+            path.to_path_buf()
+        };
+        let mut data = String::new();
+        try!(File::open(&file)
+            .and_then(|mut f| f.read_to_string(&mut data))
+            .map_err(|e| format!("{}: {}", file.display(), e)));
+        let mut skills = try!(SkillDb::parse(&data).map_err(|e| format!("{}: {}", file.display(), e)));
+        if skills.skills.is_empty() {
+            return Err(format!("{}: no skills found.", file.display()));
+        }
+        for skill in skills.skills.values_mut() {
+            if skill.category == Category::Spell && skill.tier.is_none() {
+                skill.tier = builtin.find_internal(&skill.internal).and_then(|s| s.tier);
+            }
+        }
+        Ok(skills)
+    }
+
+    /// Reads skills from the XML format of the game data. Skills without a number, name or
+    /// description are skipped; the tier of a spell is read from its `tier` attribute, which
+    /// only the built-in skills have.
+    ///
+    /// Fails if the data is not valid XML, or if two skills have the same number, name or
+    /// internal name.
+    pub fn parse(data: &str) -> Result<SkillDb, String> {
+        let mut skills: BTreeMap<usize, Skill> = BTreeMap::new();
+        let mut names: HashSet<String> = HashSet::new();
+        let mut internals: HashSet<String> = HashSet::new();
+        let elem = try!(data.parse::<xml::Element>().map_err(|e| format!("invalid XML: {}.", e)));
+        for child in elem.get_children("skill", None) {
+            let name = child.get_children("name", None).nth(0).map(|ref e| e.content_str());
+            let desc = child.get_children("description", None).nth(0).map(|ref e| e.content_str());
+            match name {
+                Some(ref name) if !name.is_empty() => {
+                    match desc {
+                        Some(ref desc) if !desc.is_empty() => {
+                            let id = match child.attributes
+                                .get(&("number".to_string(), None))
+                                .and_then(|s| s.trim().parse::<usize>().ok()) {
+                                Some(id) => id,
+                                None => continue,
+                            };
+                            let internal = child.attributes
+                                .get(&("spritename".to_string(), None)).unwrap_or(name);
+                            let weapon_type = child.get_children("wpntype", None).nth(0)
+                                .map(|ref e| e.content_str().trim().to_string())
+                                .and_then(|s| if s.is_empty() { None } else { Some(s) });
+                            let first_level = child.get_children("skill0", None).nth(0)
+                                .and_then(|ref e| parse_level(&e.content_str()))
+                                .unwrap_or(1);
+                            let mut levels: Vec<String> = (0..MAX_LEVELS)
+                                .map(|i| child.get_children(&format!("skill{}", i), None).nth(0)
+                                    .map(|ref e| format_text(&e.content_str()))
+                                    .unwrap_or(String::new()))
+                                .collect();
+                            while levels.last().map_or(false, |s| s.is_empty()) {
+                                levels.pop();
+                            }
+                            let category = if has_school(desc) {
+                                Category::Spell
+                            } else if weapon_type.is_some() || !levels.is_empty() {
+                                Category::Combat
+                            } else {
+                                Category::Aptitude
+                            };
+                            let tier = child.attributes
+                                .get(&("tier".to_string(), None))
+                                .and_then(|s| s.trim().parse::<u32>().ok())
+                                .and_then(|t| if category == Category::Spell { Some(t) } else { None });
+
+                            if skills.contains_key(&id) {
+                                return Err(format!("skill {} is listed more than once.", id));
+                            }
+                            if !names.insert(name.to_owned()) {
+                                return Err(format!("more than one skill is named {}.", name));
+                            }
+                            if !internals.insert(internal.to_owned()) {
+                                return Err(format!("more than one skill has the internal name {}.",
+                                                   internal));
+                            }
+                            skills.insert(id, Skill {
+                                name: name.to_owned(),
+                                internal: internal.to_owned(),
+                                category: category,
+                                tier: tier,
+                                weapon_type: weapon_type,
+                                description: format_text(desc),
+                                first_level: first_level,
+                                levels: levels,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(SkillDb { skills: skills })
+    }
+
+    /// Returns the number of entries in the `SkillPoints` list of a party member: one past
+    /// the highest skill index.
+    pub fn slots(&self) -> usize {
+        self.skills.keys().max().map_or(0, |&i| i + 1)
+    }
+
+    /// Returns the skill at an index.
+    pub fn get(&self, index: usize) -> Option<&Skill> {
+        self.skills.get(&index)
+    }
+
+    /// Returns the skill with the given internal name.
+    pub fn find_internal(&self, internal: &str) -> Option<&Skill> {
+        self.skills.values().find(|skill| skill.internal == internal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn builtin_categories() {
+        let skills = SkillDb::builtin();
+        assert_eq!(skills.slots(), 115);
+        for i in 0..skills.slots() {
+            let expected = match i {
+                1...6 => Category::Aptitude,
+                7...61 => Category::Combat,
+                62...114 => Category::Spell,
+                _ => {
+                    assert!(skills.get(i).is_none(), "skill {}", i);
+                    continue;
+                },
+            };
+            if let Some(skill) = skills.get(i) {
+                assert_eq!(skill.category, expected, "skill {}", i);
+                assert_eq!(skill.tier.is_some(), expected == Category::Spell, "skill {}", i);
+            }
+        }
+        assert_eq!((1..7).filter(|&i| skills.get(i).is_some()).count(), 6);
+    }
+
+    #[test]
+    fn invalid_xml_is_an_error() {
+        match SkillDb::parse("<skills><skill number=\"7\"><name>Quick</skill></skills>") {
+            Err(e) => assert!(e.starts_with("invalid XML"), "{}", e),
+            Ok(..) => panic!("expected an error"),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn search_skips_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let dir = env::temp_dir().join("sits-skill-search");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a/Data")).unwrap();
+        symlink(&dir, dir.join("a/loop")).unwrap();
+        assert_eq!(find_skills_file(&dir, MAX_SEARCH_DEPTH), None);
+
+        fs::File::create(dir.join("a/Data/Skills.xml")).unwrap();
+        assert_eq!(find_skills_file(&dir, MAX_SEARCH_DEPTH), Some(dir.join("a/Data/Skills.xml")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
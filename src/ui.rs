@@ -1,27 +1,33 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fs::copy;
-use std::path::Path;
-use std::str::FromStr;
+use std::cell::{Cell, Ref, RefCell};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use iup;
 use iup::prelude::*;
-use iup::control::{Button, Label, List, Text};
+use iup::control::{Button, Label, List, Text, Toggle};
 use iup::dialog::{FileDlg};
 use iup::element::{Handle};
 use iup::led;
 
-use time;
-use xml;
-
-use parser::{read_path, write_path};
+use parser::{read_path, recover_path};
+use points;
 use property::{Property, PropertyMap};
+use race;
+use roster;
+use rules::{self, COMBAT_SELECTS, SPELL_FAVORITES};
+use skill::{Category, Skill, SkillDb};
+use save;
 
 // Since we need to share mutable state with 'static ui callbacks,
 // we clone a refcounted cell for moving into each callback.
 type PropertyMapRc = Rc<RefCell<PropertyMap>>;
 
+// All companions, in the order of the party list, with the path each was loaded from.
+type PartyRc = Rc<RefCell<Vec<(PathBuf, PropertyMapRc)>>>;
+
+// Skills by index in the SkillPoints list, shared between callbacks.
+type SkillsRc = Rc<SkillDb>;
+
 // LED dialog specification.
 static DIALOG: &'static str = include_str!("../resources/ui.led");
 
@@ -35,30 +41,149 @@ fn from_name<E>(name: &str) -> E where E: Element {
     E::from_handle(E::from_name(name).unwrap()).unwrap()
 }
 
-// Data-bind an element to a numeric property value.
+// Show a modal message dialog.
+fn show_message(title: &str, message: &str) {
+    from_name::<Button>("button_message_ok").set_action(|_| {
+        CallbackReturn::Close
+    });
+    from_name::<Label>("label_message").set_attrib("TITLE", message.to_string());
+    let mut dlg = from_name::<Dialog>("dlg_message");
+    dlg.set_attrib("TITLE", title.to_string());
+    dlg.popup(DialogPos::CenterParent, DialogPos::CenterParent).ok();
+}
+
+// Show a modal yes/no dialog.
+//
+// @return {bool} whether the user answered yes.
+//
+fn confirm(title: &str, message: &str) -> bool {
+    let answer = Rc::new(Cell::new(false));
+    {
+        let answer = answer.clone();
+        from_name::<Button>("button_confirm_yes").set_action(move |_| {
+            answer.set(true);
+            CallbackReturn::Close
+        });
+    }
+    from_name::<Button>("button_confirm_no").set_action(|_| {
+        CallbackReturn::Close
+    });
+    from_name::<Label>("label_confirm").set_attrib("TITLE", message.to_string());
+    let mut dlg = from_name::<Dialog>("dlg_confirm");
+    dlg.set_attrib("TITLE", title.to_string());
+    dlg.popup(DialogPos::CenterParent, DialogPos::CenterParent).ok();
+    answer.get()
+}
+
+// Show a modal report of the problems found before saving.
+//
+// Saving is only offered if there are no errors.
+//
+// @return {bool} whether the user chose to save anyway.
+//
+fn show_report(report: &rules::Report) -> bool {
+    let mut text = String::new();
+    for &(title, ref lines) in [("Errors", &report.errors), ("Warnings", &report.warnings)].iter() {
+        if !lines.is_empty() {
+            text.push_str(&format!("{}:\n", title));
+            for line in lines.iter() {
+                text.push_str(&format!("- {}\n", line));
+            }
+            text.push_str("\n");
+        }
+    }
+    let summary = if report.errors.is_empty() {
+        format!("{} warnings were found. Save anyway?", report.warnings.len())
+    } else {
+        format!("{} errors must be fixed before saving.", report.errors.len())
+    };
+
+    let answer = Rc::new(Cell::new(false));
+    {
+        let answer = answer.clone();
+        let mut button_save = from_name::<Button>("button_report_save");
+        button_save.set_attrib("ACTIVE", if report.errors.is_empty() { "YES" } else { "NO" });
+        button_save.set_action(move |_| {
+            answer.set(true);
+            CallbackReturn::Close
+        });
+    }
+    from_name::<Button>("button_report_cancel").set_action(|_| {
+        CallbackReturn::Close
+    });
+    from_name::<Label>("label_report").set_attrib("TITLE", summary);
+    from_name::<Text>("text_report").set_attrib("VALUE", text);
+    let mut dlg = from_name::<Dialog>("dlg_report");
+    dlg.popup(DialogPos::CenterParent, DialogPos::CenterParent).ok();
+    answer.get()
+}
+
+// Read a property file, offering to recover what can be salvaged if its data is damaged.
+//
+// Nothing is written here; recovered data replaces the damaged file on save, after the
+// damaged file is backed up.
+//
+// @param recovered {Vec} paths of the files loaded by recovery, to which the path is added.
+//
+fn load_path(path: &Path, recovered: &mut Vec<PathBuf>) -> Result<PropertyMap, String> {
+    let err = match read_path(path) {
+        Ok(v) => return Ok(v),
+        Err(ref e) if !e.is_damage() => return Err(format!("Cannot read {:?}: {}", path, e)),
+        Err(e) => format!("Cannot read {:?}: {}", path, e),
+    };
+    if !confirm("Damaged file", &format!(
+        "{}\n\nLoad the properties that can be recovered? The damaged file will be backed up\nand replaced with the recovered properties when you save.",
+        err)) {
+        return Err(err);
+    }
+    let res = try!(recover_path(path).map_err(|e| format!("Cannot read {:?}: {}", path, e)));
+    show_message("Damaged file", &format!(
+        "Recovered {} properties from {:?}; skipped {} damaged regions.",
+        res.props.len(), path, res.damaged.len()));
+    recovered.push(path.to_path_buf());
+    Ok(res.props)
+}
+
+// Data-bind an element to a property value.
 //
 // Value of the element is set to the current value of the property, and
-// changes to element value are written back to the property map.
+// changes to element value are written back to the property map, parsed as
+// the same type of property; numbers that cannot be parsed are written as 0.
+// The element is disabled if the property is missing.
 //
 // @param props {PropertyMapRc} a cloned refcounted property map.
 //
-fn bind<T, E>(elem: &mut E, props: PropertyMapRc, key: &'static str)
-    where Property: From<T>,
-          T: FromStr + Default,
-          E: Element + ValueChangedCb {
+fn bind<E>(elem: &mut E, props: PropertyMapRc, key: &'static str)
+    where E: Element + ValueChangedCb {
+    bind_with(elem, props, key, |_: &PropertyMapRc| {});
+}
+
+// Data-bind an element to a property value, like `bind`, calling a function after each
+// change is written back.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param on_change {Fn} called with the property map after each change.
+//
+fn bind_with<E, F>(elem: &mut E, props: PropertyMapRc, key: &'static str, on_change: F)
+    where E: Element + ValueChangedCb,
+          F: Fn(&PropertyMapRc) + 'static {
 
     // Remove previous bindings, if any.
     elem.remove_valuechanged_cb();
 
     if let Some(ref prop) = props.borrow().get(key) {
+        elem.set_attrib("ACTIVE", "YES");
         elem.set_attrib("VALUE", prop.to_string());
+    } else {
+        elem.set_attrib("ACTIVE", "NO");
     }
     elem.set_valuechanged_cb(move |(elem,): (E,)| {
         if let Some(ref value) = elem.attrib("VALUE") {
-            if let Ok(v) = T::from_str(value) {
-                props.borrow_mut().insert(key.to_string(), Property::from(v));
-            } else {
-                props.borrow_mut().insert(key.to_string(), Property::from(T::default()));
+            let prop = props.borrow().get(key)
+                .and_then(|p| p.parse_like(value).or_else(|| p.parse_like("0")));
+            if let Some(prop) = prop {
+                props.borrow_mut().insert(key.to_string(), prop);
+                on_change(&props);
             }
         }
     });
@@ -73,6 +198,18 @@ fn bind<T, E>(elem: &mut E, props: PropertyMapRc, key: &'static str)
 //
 fn bind_list<E>(elem: &mut E, props: PropertyMapRc, key: &'static str, index: usize)
     where E: Element + ValueChangedCb {
+    bind_list_with(elem, props, key, index, |_: &PropertyMapRc| {});
+}
+
+// Data-bind an element to a list property value, at given index, like `bind_list`,
+// calling a function after each change is written back.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param on_change {Fn} called with the property map after each change.
+//
+fn bind_list_with<E, F>(elem: &mut E, props: PropertyMapRc, key: &'static str, index: usize, on_change: F)
+    where E: Element + ValueChangedCb,
+          F: Fn(&PropertyMapRc) + 'static {
 
     // Remove previous bindings, if any.
     elem.remove_valuechanged_cb();
@@ -85,20 +222,190 @@ fn bind_list<E>(elem: &mut E, props: PropertyMapRc, key: &'static str, index: us
             if let Some(&mut Property::List(ref mut v)) = props.borrow_mut().get_mut(key) {
                 v[index] = value.to_string();
             }
+            on_change(&props);
+        }
+    });
+}
+
+// Data-bind a toggle element to a boolean property value.
+//
+// The toggle is checked if the property is true, and disabled if it is missing or not
+// a boolean; changes to the toggle are written back to the property map.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+//
+fn bind_toggle(elem: &mut Toggle, props: PropertyMapRc, key: &'static str) {
+
+    // Remove previous bindings, if any.
+    elem.remove_valuechanged_cb();
+
+    if let Some(&Property::Boolean(v)) = props.borrow().get(key) {
+        elem.set_attrib("ACTIVE", "YES");
+        elem.set_attrib("VALUE", if v { "ON" } else { "OFF" });
+    } else {
+        elem.set_attrib("ACTIVE", "NO");
+        elem.set_attrib("VALUE", "OFF");
+    }
+    elem.set_valuechanged_cb(move |(elem,): (Toggle,)| {
+        if let Some(ref value) = elem.attrib("VALUE") {
+            props.borrow_mut().insert(key.to_string(), Property::Boolean(value == "ON"));
+        }
+    });
+}
+
+// Data-bind a text element to a numeric property, like `bind`, for values whose meaning
+// is not known, such as the gender or portrait of a party member. The element is disabled
+// unless the property is a number.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+//
+fn bind_number(elem: &mut Text, props: PropertyMapRc, key: &'static str) {
+    let is_number = match props.borrow().get(key) {
+        Some(&Property::Integer(..)) | Some(&Property::Float(..)) => true,
+        _ => false,
+    };
+    bind(elem, props, key);
+    if !is_number {
+        elem.set_attrib("ACTIVE", "NO");
+    }
+}
+
+// Returns whether a party member name can be used: a blank name would show as a blank
+// label in the party list.
+fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty()
+}
+
+// Returns the label of a party member in the party list, "Name (Level)".
+fn member_label(member: &PropertyMap) -> String {
+    let name = match member.get("Name") {
+        Some(&Property::String(ref name)) => name.to_string(),
+        _ => "(unnamed)".to_string(),
+    };
+    match member.get("Level") {
+        Some(&Property::Float(level)) => format!("{} ({})", name, level),
+        _ => name,
+    }
+}
+
+// Returns the entry of a party member in the party list, marked as in the party or benched.
+fn party_list_label(member: &PropertyMap, in_party: bool) -> String {
+    format!("{} {}", if in_party { "[Party]" } else { "[Bench]" }, member_label(member))
+}
+
+// Update the party list entry of a party member.
+fn update_member_label(props: &PropertyMapRc, index: usize, in_party: bool) {
+    let mut list_party = from_name::<List>("list_party");
+    list_party.set_attrib((index + 1).to_string(), party_list_label(&props.borrow(), in_party));
+    list_party.set_attrib("VALUE", (index + 1).to_string());
+}
+
+// Data-bind a text element to the name of a party member.
+//
+// Changes are only written back to the property map if the name is not blank; blank
+// names are highlighted instead. The element is disabled if the name is missing or not a
+// string. The party list entry of the member is kept up to date.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param index {usize} index of the member in the party list.
+// @param in_party {bool} whether the member is in the party, rather than benched.
+//
+fn bind_name(elem: &mut Text, props: PropertyMapRc, index: usize, in_party: bool) {
+
+    // Remove previous bindings, if any.
+    elem.remove_valuechanged_cb();
+
+    if let Some(&Property::String(ref name)) = props.borrow().get("Name") {
+        elem.set_attrib("ACTIVE", "YES");
+        elem.set_attrib("VALUE", name.to_string());
+    } else {
+        elem.set_attrib("ACTIVE", "NO");
+        elem.set_attrib("VALUE", "");
+    }
+    elem.set_attrib("BGCOLOR", "255 255 255");
+    elem.set_valuechanged_cb(move |(mut elem,): (Text,)| {
+        if let Some(ref value) = elem.attrib("VALUE") {
+            if is_valid_name(value) {
+                elem.set_attrib("BGCOLOR", "255 255 255");
+                if let Some(&mut Property::String(ref mut name)) = props.borrow_mut().get_mut("Name") {
+                    *name = value.to_string();
+                }
+                update_member_label(&props, index, in_party);
+            } else {
+                elem.set_attrib("BGCOLOR", "255 200 200");
+            }
+        }
+    });
+}
+
+// Data-bind a dropdown list element to the race of a party member.
+//
+// Only the race is changed; stats, which include racial modifiers, are left as they are.
+// The element is disabled unless the race is one of the choices.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param races {Vec} races to choose from, see `race::races`.
+//
+fn bind_race(elem: &mut List, props: PropertyMapRc, races: Vec<String>) {
+
+    // Remove previous bindings, if any.
+    elem.remove_action();
+
+    elem.set_items(races.clone());
+    let selected = race::race_of(&props.borrow()).and_then(|r| races.iter().position(|x| x == r));
+    match selected {
+        Some(i) => {
+            elem.set_attrib("ACTIVE", "YES");
+            elem.set_attrib("VALUE", (i + 1).to_string());
+        },
+        None => {
+            elem.set_attrib("ACTIVE", "NO");
+            elem.set_attrib("VALUE", "0");
+        },
+    }
+    elem.set_action(move |(_, _, i, state)| {
+        if state != 1 {
+            return;
+        }
+        if let Some(race) = races.get(i as usize - 1) {
+            if let Err(e) = race::set_race(&mut props.borrow_mut(), race) {
+                show_message("Cannot change race", &e);
+            }
         }
     });
 }
 
+// Data-bind text elements to the experience and level of a party member.
+//
+// The party list entry of the member is kept up to date with its level.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param index {usize} index of the member in the party list.
+// @param in_party {bool} whether the member is in the party, rather than benched.
+//
+fn bind_level(props: PropertyMapRc, index: usize, in_party: bool) {
+    bind(&mut from_name::<Text>("text_xp"), props.clone(), "XP");
+    bind_with(&mut from_name::<Text>("text_level"), props, "Level",
+              move |props: &PropertyMapRc| update_member_label(props, index, in_party));
+}
+
 macro_rules! bind_stat {
     ($i:ident, $p:expr, $e:expr) => {
-        bind::<f32,_>(&mut from_name::<Text>(stringify!($i)), $p.clone(), $e);
-    }
+        bind(&mut from_name::<Text>(stringify!($i)), $p.clone(), $e);
+    };
+    ($i:ident, $p:expr, $e:expr, $s:expr, $f:ident) => {{
+        let skills = $s.clone();
+        bind_with(&mut from_name::<Text>(stringify!($i)), $p.clone(), $e,
+                  move |props: &PropertyMapRc| $f(props, &skills));
+    }}
 }
 
 macro_rules! bind_skill {
-    ($i:ident, $p:expr, $n:expr) => {
-        bind_list::<_>(&mut from_handle::<Text>($i), $p.clone(), "SkillPoints", $n);
-    }
+    ($i:ident, $p:expr, $n:expr, $s:expr) => {{
+        let skills = $s.clone();
+        bind_list_with::<_, _>(&mut from_handle::<Text>($i), $p.clone(), "SkillPoints", $n,
+                               move |props: &PropertyMapRc| skills_changed(props, &skills));
+    }}
 }
 
 // Creates a pair of (label, text) for inputting numeric values.
@@ -123,16 +430,195 @@ fn make_control_pair(title: Option<&String>) -> (Label, Text) {
     (label, text)
 }
 
-// Data-bind all elements relevant to a party member.
+// Data-bind the stats of a party member.
+//
+// Stats that make up the combat and spell grades are updated live.
 //
 // @param props {PropertyMapRc} a cloned refcounted property map.
+// @param skills {SkillsRc} skills by index.
 //
-fn bind_member(props: PropertyMapRc) {
-    bind_stat!(text_int, props, "Int");
-    bind_stat!(text_dex, props, "Dex");
-    bind_stat!(text_str, props, "Str");
-    bind_stat!(text_occ, props, "Occ");
+fn bind_stats(props: PropertyMapRc, skills: SkillsRc) {
+    bind_stat!(text_int, props, "Int", skills, stats_changed);
+    bind_stat!(text_dex, props, "Dex", skills, stats_changed);
+    bind_stat!(text_str, props, "Str", skills, stats_changed);
+    bind_stat!(text_occ, props, "Occ", skills, stats_changed);
     bind_stat!(text_per, props, "Per");
+}
+
+// Data-bind the unallocated stat and skill points of a party member.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+//
+fn bind_points(props: PropertyMapRc) {
+    bind_stat!(text_stat_points,  props, points::STAT_POINTS);
+    bind_stat!(text_skill_points, props, points::SKILL_POINTS);
+}
+
+// Data-bind the aptitudes and skills of a party member.
+//
+// Skill tips are updated as they change.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param skills {SkillsRc} skills by index.
+//
+fn bind_skills(props: PropertyMapRc, skills: SkillsRc) {
+    if let Some(&mut Property::List(ref mut v)) = props.borrow_mut().get_mut("SkillPoints") {
+        while v.len() < skills.slots() {
+            v.push("0".to_string())
+        }
+    }
+    for (grid, indices) in skill_layout(&skills) {
+        if let Some(grid) = Handle::from_named(grid) {
+            for (pos, &i) in indices.iter().enumerate() {
+                if let Some(child) = grid.child(pos * 2 + 1) {
+                    bind_skill!(child, props, i, skills);
+                }
+            }
+        }
+    }
+}
+
+// Returns the choices for a hotbar slot: empty, or any of the known skills.
+//
+// @param known {&[String]} internal names of the known skills, see `rules::known_skills`.
+// @param empty {&str} value of an empty slot.
+//
+fn slot_choices(known: &[String], skills: &SkillDb, empty: &str) -> Vec<(String, String)> {
+    let mut choices = vec![(empty.to_string(), "(empty)".to_string())];
+    for internal in known.iter() {
+        let name = skills.find_internal(internal)
+            .map(|skill| skill.name.clone())
+            .unwrap_or(internal.clone());
+        choices.push((internal.clone(), name));
+    }
+    choices
+}
+
+// Hotbars of a party member: grid element, list property, number of slots and value of an
+// empty slot.
+static HOTBARS: [(&'static str, &'static str, usize, &'static str); 2] = [
+    ("combat_grid", "CombatSelects", COMBAT_SELECTS, "Empty"),
+    ("spell_grid", "SpellFavorites", SPELL_FAVORITES, ""),
+];
+
+// Data-bind a dropdown list element to a slot of a hotbar list property.
+//
+// Skills already in another slot of the hotbar are left out of the choices. A skill in the
+// slot that is no longer known is kept as a choice, marked as such; it is cleared on save.
+// The element is disabled if the property is missing.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param bar {usize} index of the hotbar in `HOTBARS`.
+// @param known {Vec} pairs of (value, title), see `slot_choices`.
+//
+fn bind_slot(elem: &mut List, props: PropertyMapRc, bar: usize, index: usize,
+             known: Vec<(String, String)>) {
+    let (_, key, _, empty) = HOTBARS[bar];
+
+    // Remove previous bindings, if any.
+    elem.remove_action();
+
+    let (value, used) = match props.borrow().get(key) {
+        Some(&Property::List(ref v)) => {
+            let used = v.iter().enumerate()
+                .filter(|&(i, x)| i != index && x != empty)
+                .map(|(_, x)| x.clone())
+                .collect::<Vec<String>>();
+            (v.get(index).cloned(), used)
+        },
+        _ => (None, Vec::new()),
+    };
+    let mut choices = known.iter()
+        .filter(|c| !used.contains(&c.0))
+        .cloned()
+        .collect::<Vec<(String, String)>>();
+    match value {
+        Some(value) => {
+            if !choices.iter().any(|c| c.0 == value) {
+                choices.push((value.clone(), format!("{} (not known)", value)));
+            }
+            elem.set_items(choices.iter().map(|c| c.1.clone()).collect::<Vec<String>>());
+            elem.set_attrib("ACTIVE", "YES");
+            elem.set_attrib("VALUE", match choices.iter().position(|c| c.0 == value) {
+                Some(i) => (i + 1).to_string(),
+                None => "0".to_string(),
+            });
+        },
+        None => {
+            elem.set_items(Vec::<String>::new());
+            elem.set_attrib("ACTIVE", "NO");
+        },
+    }
+    elem.set_action(move |(_, _, i, state)| {
+        if state != 1 {
+            return;
+        }
+        if let Some(c) = choices.get(i as usize - 1) {
+            if let Some(&mut Property::List(ref mut v)) = props.borrow_mut().get_mut(key) {
+                if index < v.len() {
+                    v[index] = c.0.clone();
+                }
+            }
+            // Update the choices of the other slots.
+            bind_slots(props.clone(), bar, &known, Some(index));
+        }
+    });
+}
+
+// Data-bind the slots of a hotbar.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param bar {usize} index of the hotbar in `HOTBARS`.
+// @param known {&[(String, String)]} pairs of (value, title), see `slot_choices`.
+// @param skip {Option<usize>} slot to leave as bound, if any.
+//
+fn bind_slots(props: PropertyMapRc, bar: usize, known: &[(String, String)], skip: Option<usize>) {
+    let (grid, _, slots, _) = HOTBARS[bar];
+    if let Some(grid) = Handle::from_named(grid) {
+        for i in (0..slots).filter(|&i| Some(i) != skip) {
+            if let Some(child) = grid.child(i * 2 + 1) {
+                bind_slot(&mut from_handle::<List>(child), props.clone(), bar, i, known.to_vec());
+            }
+        }
+    }
+}
+
+// Data-bind the combat selects and spell favorites of a party member.
+//
+// Choices are limited to the skills known by the member.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+//
+fn bind_hotbar(props: PropertyMapRc, skills: &SkillDb) {
+    let (combat_skills, spell_skills) = rules::known_skills(&props.borrow(), skills);
+    for (bar, known) in [combat_skills, spell_skills].iter().enumerate() {
+        let (_, key, slots, empty) = HOTBARS[bar];
+        if let Some(&mut Property::List(ref mut v)) = props.borrow_mut().get_mut(key) {
+            while v.len() < slots {
+                v.push(empty.to_string())
+            }
+        }
+        bind_slots(props.clone(), bar, &slot_choices(known, skills, empty), None);
+    }
+}
+
+// Data-bind all elements relevant to a party member.
+//
+// @param props {PropertyMapRc} a cloned refcounted property map.
+// @param index {usize} index of the member in the party list.
+// @param in_party {bool} whether the member is in the party, rather than benched.
+// @param races {Vec} races to choose from, see `race::races`.
+// @param skills {SkillsRc} skills by index.
+//
+fn bind_member(props: PropertyMapRc, index: usize, in_party: bool, races: Vec<String>, skills: SkillsRc) {
+    bind_name(&mut from_name::<Text>("text_name"), props.clone(), index, in_party);
+    bind_number(&mut from_name::<Text>("text_gender"), props.clone(), "Gender");
+    bind_number(&mut from_name::<Text>("text_portrait"), props.clone(), "Portrait");
+    bind_race(&mut from_name::<List>("list_race"), props.clone(), races);
+    bind_level(props.clone(), index, in_party);
+    bind_toggle(&mut from_name::<Toggle>("toggle_bound"), props.clone(), "Bound");
+
+    bind_stats(props.clone(), skills.clone());
 
     bind_stat!(text_hp_cur, props, "CurrHealth");
     bind_stat!(text_hp_max, props, "MaxHealth");
@@ -146,137 +632,316 @@ fn bind_member(props: PropertyMapRc) {
     bind_stat!(text_wpn_xbow,   props, "WpnXbow");
     bind_stat!(text_wpn_elixir, props, "WpnElixir");
 
-    if let Some(&mut Property::List(ref mut v)) = props.borrow_mut().get_mut("SkillPoints") {
-        while v.len() < 115 {
-            v.push("0".to_string())
+    bind_points(props.clone());
+    bind_skills(props.clone(), skills.clone());
+    bind_hotbar(props.clone(), &skills);
+    update_grades(&props, &skills);
+    update_skill_tips(&props, &skills);
+
+    let mut button_refund = from_name::<Button>("button_refund");
+    button_refund.set_action(move |_| {
+        let refunded = points::refund_skill_points(&mut props.borrow_mut(), &skills);
+        match refunded {
+            Ok(_) => {
+                bind_points(props.clone());
+                bind_skills(props.clone(), skills.clone());
+                skills_changed(&props, &skills);
+            },
+            Err(e) => show_message("Refund failed", &e),
         }
+    });
+}
+
+// Returns the entries of all companions in the party list.
+fn party_list_items(game: &PropertyMapRc, party: &PartyRc) -> Vec<String> {
+    party.borrow().iter().map(|&(_, ref member)| {
+        let member = member.borrow();
+        party_list_label(&member, roster::in_party(&game.borrow(), &member))
+    }).collect()
+}
+
+// Enable the roster buttons that apply to a companion in the party, or benched.
+fn update_roster_buttons(in_party: bool) {
+    let (yes, no) = if in_party { ("YES", "NO") } else { ("NO", "YES") };
+    from_name::<Button>("button_move_up").set_attrib("ACTIVE", yes);
+    from_name::<Button>("button_move_down").set_attrib("ACTIVE", yes);
+    from_name::<Button>("button_bench").set_attrib("ACTIVE", yes);
+    from_name::<Button>("button_add").set_attrib("ACTIVE", no);
+}
+
+// Select a companion in the party list, and data-bind all elements relevant to it.
+//
+// @param skills {SkillsRc} skills by index.
+// @param index {usize} index of the companion in the party list.
+//
+fn select_member(game: &PropertyMapRc, party: &PartyRc, skills: &SkillsRc, index: usize) {
+    let member = party.borrow()[index].1.clone();
+    let in_party = roster::in_party(&game.borrow(), &member.borrow());
+    from_name::<List>("list_party").set_attrib("VALUE", (index + 1).to_string());
+    update_roster_buttons(in_party);
+    let races = {
+        let party = party.borrow();
+        let members: Vec<Ref<PropertyMap>> = party.iter().map(|&(_, ref m)| m.borrow()).collect();
+        race::races(members.iter().map(|m| &**m))
+    };
+    bind_member(member, index, in_party, races, skills.clone());
+}
+
+// Change the party listed in the game file for the selected companion, then reorder the
+// party list to match and select the companion again.
+//
+// @param change {Fn} roster change, given the game properties and the companion id.
+//
+fn edit_roster<F>(game: &PropertyMapRc, party: &PartyRc, skills: &SkillsRc, change: F)
+    where F: Fn(&mut PropertyMap, &str) -> Result<(), String> {
+
+    let mut list_party = from_name::<List>("list_party");
+    let index = match list_party.attrib("VALUE").and_then(|v| v.parse::<usize>().ok()) {
+        Some(i) if i > 0 && i <= party.borrow().len() => i - 1,
+        _ => return,
+    };
+    let member = party.borrow()[index].1.clone();
+    let id = match roster::id_of(&member.borrow()).map(String::from) {
+        Some(id) => id,
+        None => return,
+    };
+    if let Err(e) = change(&mut game.borrow_mut(), &id) {
+        show_message("Party", &e);
+        return;
     }
-    if let Some(apt_grid) = Handle::from_named("apt_grid") {
-        for i in 1..7 {
-            if let Some(child) = apt_grid.child((i - 1) * 2 + 1) {
-                bind_skill!(child, props, i);
+
+    party.borrow_mut().sort_by_key(|&(_, ref m)| roster::sort_key(&game.borrow(), &m.borrow()));
+    list_party.set_items(party_list_items(game, party));
+    let index = party.borrow().iter()
+        .position(|&(_, ref m)| roster::id_of(&m.borrow()) == Some(&id[..]))
+        .unwrap_or(0);
+    select_member(game, party, skills, index);
+}
+
+// Returns a description of the grade from two stats, and of the next threshold to reach.
+fn grade_label(member: &PropertyMap, title: &str, first: &str, second: &str) -> String {
+    let total = match rules::grade_total(member, first, second) {
+        Some(total) => total,
+        None => return format!("{} grade: unknown", title),
+    };
+    let grade = rules::grade(member, first, second);
+    match rules::GRADE_THRESHOLDS.get(grade as usize) {
+        Some(next) => format!("{} grade: {} ({} + {} = {}, grade {} at {})",
+                              title, grade, first, second, total, grade + 1.0, next),
+        None => format!("{} grade: {} ({} + {} = {}, highest grade)",
+                        title, grade, first, second, total),
+    }
+}
+
+// Update the combat and spell grade labels of a party member, and disable the spell
+// skills of the tiers that the spell grade does not unlock; those with points stay
+// enabled, so that the points can be taken back.
+fn update_grades(props: &PropertyMapRc, skills: &SkillDb) {
+    let member = props.borrow();
+    from_name::<Label>("label_combat_grade")
+        .set_attrib("TITLE", grade_label(&member, "Combat", "Str", "Dex"));
+    from_name::<Label>("label_spell_grade")
+        .set_attrib("TITLE", grade_label(&member, "Spell", "Int", "Occ"));
+
+    let spell_grade = rules::spell_grade(&member);
+    for i in 1..skills.slots() {
+        let active = match rules::spell_tier(skills, i) {
+            Some(tier) if tier > spell_grade && rules::skill_points(&member, i) == 0 => "NO",
+            Some(..) => "YES",
+            None => continue,
+        };
+        for j in 0..2 {
+            if let Some(mut child) = skill_control(skills, i, j) {
+                child.set_attrib("ACTIVE", active);
             }
         }
     }
-    if let Some(skill_grid) = Handle::from_named("skill_grid") {
-        for i in 7..115 {
-            if let Some(child) = skill_grid.child((i - 7) * 2 + 1) {
-                bind_skill!(child, props, i);
-            }
+}
+
+// Returns the indices of the skills shown in each grid: aptitudes in the aptitude grid,
+// and every other slot in the skill grid, including those without a skill.
+fn skill_layout(skills: &SkillDb) -> Vec<(&'static str, Vec<usize>)> {
+    let is_aptitude = |i: usize| skills.get(i).map_or(false, |s| s.category == Category::Aptitude);
+    vec![
+        ("apt_grid", (1..skills.slots()).filter(|&i| is_aptitude(i)).collect::<Vec<usize>>()),
+        ("skill_grid", (1..skills.slots()).filter(|&i| !is_aptitude(i)).collect::<Vec<usize>>()),
+    ]
+}
+
+// Returns a control of a skill in the aptitude or skill grid, by its index.
+// @param column 0 for the label, 1 for the text.
+fn skill_control(skills: &SkillDb, index: usize, column: usize) -> Option<Handle> {
+    for (grid, indices) in skill_layout(skills) {
+        if let Some(pos) = indices.iter().position(|&i| i == index) {
+            return Handle::from_named(grid).and_then(|grid| grid.child(pos * 2 + column));
         }
     }
+    None
 }
 
-fn calculate_grade(props: &PropertyMapRc, first: &str, second: &str) -> f32 {
-    if let Some(&Property::Float(first_mod)) = props.borrow().get(first) {
-        if let Some(&Property::Float(second_mod)) = props.borrow().get(second) {
-            let total = first_mod + second_mod + 20.0;
-            if total >= 32.0 {
-                return 3.0;
-            } else if total >= 26.0 {
-                return 2.0;
-            } else if total >= 21.0 {
-                return 1.0;
-            }
+// Returns the tip of a skill: its name and the weapons it is used with, followed by its
+// effects at the current and next levels, or its description if it has no levels.
+fn skill_tip(skill: &Skill, level: u32) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    paragraphs.push(skill.name.clone());
+    if let Some(ref weapon_type) = skill.weapon_type {
+        paragraphs.push(format!("Used with: {}", weapon_type));
+    }
+    if skill.levels.is_empty() {
+        paragraphs.push(skill.description.clone());
+    } else {
+        if let Some(text) = skill.level_text(level) {
+            paragraphs.push(text.to_string());
         }
-    };
-    return 0.0;
+        match skill.level_text(level + 1) {
+            Some(text) => paragraphs.push(format!("Next: {}", text)),
+            None if skill.max_level().map_or(false, |max| level >= max) => {
+                paragraphs.push("Fully learned.".to_string())
+            },
+            None => {}
+        }
+    }
+    paragraphs.join("\n\n")
+}
+
+// Update the tip of each skill of a party member with its effects at the current level.
+fn update_skill_tips(props: &PropertyMapRc, skills: &SkillDb) {
+    let member = props.borrow();
+    for i in 1..skills.slots() {
+        let skill = match skills.get(i) {
+            Some(skill) => skill,
+            None => continue,
+        };
+        let tip = skill_tip(skill, rules::skill_points(&member, i));
+        if let Some(mut label) = skill_control(skills, i, 0) {
+            label.set_attrib("TIP", tip.clone());
+        }
+        if let Some(mut text) = skill_control(skills, i, 1) {
+            text.set_attrib("TIP", tip);
+        }
+    }
+}
+
+// Update everything that depends on the skill points of a party member.
+fn skills_changed(props: &PropertyMapRc, skills: &SkillsRc) {
+    update_grades(props, skills);
+    update_skill_tips(props, skills);
+    bind_hotbar(props.clone(), skills);
+}
+
+// Update everything that depends on the stats of a party member.
+fn stats_changed(props: &PropertyMapRc, skills: &SkillsRc) {
+    update_grades(props, skills);
+    bind_hotbar(props.clone(), skills);
 }
 
 /// Ui entry point.
 ///
-/// Starts by showing a direction selection dialog; after the user selects a directory,
-/// the game is loaded from that directory and values bound to the ui elements.
+/// Unless a directory is given, starts by showing a directory selection dialog; the game is
+/// then loaded from that directory and values bound to the ui elements.
 ///
-pub fn ui_loop() -> Result<(), String> {
-    match iup::with_iup(|| {
+pub fn ui_loop(dir: Option<String>, skills: SkillDb) -> Result<(), String> {
+    match iup::with_iup(move || {
         // See also led::load(path) to load from a file
         led::load_buffer(DIALOG).unwrap();
 
         // Select saved game location
-        let mut dlg_open = from_name::<FileDlg>("dlg_open");
-        let dir = match dlg_open.popup(DialogPos::CenterParent, DialogPos::CenterParent) {
-            Ok(..) => match dlg_open.attrib("STATUS") {
-                Some(ref s) if s == "0" => {
-                    dlg_open.attrib("VALUE").unwrap()
-                },
-                _ => return Err("File selection cancelled.".to_string())
-            },
-            _ => return Err("File selection failed.".to_string())
+        let dir = match dir.clone() {
+            Some(dir) => dir,
+            None => {
+                let mut dlg_open = from_name::<FileDlg>("dlg_open");
+                match dlg_open.popup(DialogPos::CenterParent, DialogPos::CenterParent) {
+                    Ok(..) => match dlg_open.attrib("STATUS") {
+                        Some(ref s) if s == "0" => {
+                            dlg_open.attrib("VALUE").unwrap()
+                        },
+                        _ => return Err("File selection cancelled.".to_string())
+                    },
+                    _ => return Err("File selection failed.".to_string())
+                }
+            }
         };
 
         // Read game and party member files
+        let mut recovered: Vec<PathBuf> = Vec::new();
         let game = Rc::new(RefCell::new({
-            let path = Path::new(&dir).join("Game.txt");
-            match read_path(path.as_path()) {
-                Ok(v) => v,
-                Err(e) => {
-                    return Err(format!("Cannot read {:?}: {}", path, e))
-                }
-            }
+            let path = save::game_path(Path::new(&dir));
+            try!(load_path(path.as_path(), &mut recovered))
         }));
         let party = Rc::new(RefCell::new({
-            let mut members: Vec<PropertyMapRc> = Vec::new();
-            if let Some(&Property::String(ref ids)) = game.borrow().get("PartyIDs") {
-                for id in ids.split(",") {
-                    if id == "0" {
-                        continue;
-                    }
-                    let path = Path::new(&dir).join("Party".to_string() + id + ".txt");
-                    match read_path(path.as_path()) {
-                        Ok(v) => members.push(Rc::new(RefCell::new(v))),
-                        Err(e) => {
-                            return Err(format!("Cannot read {:?}: {}", path, e))
-                        }
-                    };
-                }
+            let mut members: Vec<(PathBuf, PropertyMapRc)> = Vec::new();
+            for id in try!(save::companion_ids(Path::new(&dir), &game.borrow())) {
+                let path = save::party_path(Path::new(&dir), &id);
+                let member = try!(load_path(path.as_path(), &mut recovered));
+                members.push((path, Rc::new(RefCell::new(member))));
             }
             members
         }));
+        let recovered = Rc::new(RefCell::new(recovered));
 
         let mut text_emeralds = from_name::<Text>("text_emeralds");
-        bind::<u32,_>(&mut text_emeralds, game.clone(), "Emeralds");
+        bind(&mut text_emeralds, game.clone(), "Emeralds");
 
-        let mut list_party = from_name::<List>("list_party");
-        let mut list_party_items: Vec<String> = Vec::new();
-        for member in party.borrow().iter() {
-            if let Some(&Property::String(ref name)) = member.borrow().get("Name") {
-                if let Some(&Property::Float(level)) = member.borrow().get("Level") {
-                    list_party_items.push(format!("{} ({})", name, level));
+        let skills: SkillsRc = Rc::new(skills);
+        for (grid, indices) in skill_layout(&skills) {
+            if let Some(mut grid) = Handle::from_named(grid) {
+                while let Some(mut child) = grid.child(0) {
+                    child.detach().destroy();
+                }
+                for &i in indices.iter() {
+                    let (label, text) = make_control_pair(skills.get(i).map(|ref x| &(x.name)));
+                    grid.append(label).unwrap();
+                    grid.append(text).unwrap();
                 }
             }
         }
 
-        let skills = load_skills();
-        if let Some(mut apt_grid) = Handle::from_named("apt_grid") {
-            while let Some(mut child) = apt_grid.child(0) {
-                child.detach().destroy();
-            }
-            for i in 1..7 {
-                let (label, text) = make_control_pair(skills.get(&i).map(|ref x| &(x.name)));
-                apt_grid.append(label).unwrap();
-                apt_grid.append(text).unwrap();
+        for &(name, _, slots, _) in HOTBARS.iter() {
+            if let Some(mut grid) = Handle::from_named(name) {
+                while let Some(mut child) = grid.child(0) {
+                    child.detach().destroy();
+                }
+                for i in 0..slots {
+                    let label = Label::new()
+                        .set_attrib("SIZE", "x11".to_string())
+                        .set_attrib("TITLE", (i + 1).to_string());
+                    let list = List::new_dropdown()
+                        .set_attrib("SIZE", "90x12".to_string())
+                        .set_attrib("VISIBLE_ITEMS", "12".to_string());
+                    grid.append(label).unwrap();
+                    grid.append(list).unwrap();
+                }
             }
         }
-        if let Some(mut skill_grid) = Handle::from_named("skill_grid") {
-            while let Some(mut child) = skill_grid.child(0) {
-                child.detach().destroy();
-            }
-            for i in 7..115 {
-                let (label, text) = make_control_pair(skills.get(&i).map(|ref x| &(x.name)));
-                skill_grid.append(label).unwrap();
-                skill_grid.append(text).unwrap();
-            }
+
+        let mut list_party = from_name::<List>("list_party");
+        list_party.set_items(party_list_items(&game, &party));
+        {
+            let game_clone = game.clone();
+            let party_clone = party.clone();
+            let skills_clone = skills.clone();
+            list_party.set_action(move |(_, _, i, state)| {
+                if state == 1 {
+                    select_member(&game_clone, &party_clone, &skills_clone, i as usize - 1);
+                }
+            });
+        }
+        if !party.borrow().is_empty() {
+            select_member(&game, &party, &skills, 0);
         }
 
-        let party_clone = party.clone();
-        list_party.set_items(list_party_items);
-        list_party.set_action(move |(_, _, i, _)| {
-            let member = party_clone.borrow()[i as usize - 1].clone();
-            bind_member(member);
-        });
-        if let Some(&ref member) = party.borrow().first() {
-            bind_member(member.clone());
+        // Change the party on roster buttons
+        for &(name, change) in [
+                ("button_move_up", roster::move_up as fn(&mut PropertyMap, &str) -> Result<(), String>),
+                ("button_move_down", roster::move_down),
+                ("button_add", roster::add),
+                ("button_bench", roster::remove)].iter() {
+            let game_clone = game.clone();
+            let party_clone = party.clone();
+            let skills_clone = skills.clone();
+            from_name::<Button>(name).set_action(move |_| {
+                edit_roster(&game_clone, &party_clone, &skills_clone, change);
+            });
         }
 
         // Write game and party member files on save
@@ -284,73 +949,50 @@ pub fn ui_loop() -> Result<(), String> {
         {
             let game_clone = game.clone();
             let party_clone = party.clone();
+            let recovered = recovered.clone();
             button_save.set_action(move |_| {
-                let timestamp = time::strftime("%FT%H.%M.%SZ.txt", &time::now_utc()).unwrap();
-                let path = Path::new(&dir).join("Game.txt");
-                copy(path.as_path(), path.with_extension(&timestamp)).unwrap();
-                write_path(path.as_path(), &game_clone.borrow()).unwrap();
-                for member in party_clone.borrow().iter() {
-
-                    // Validate
-                    let combat_grade = calculate_grade(member, "Str", "Dex");
-                    let mut combat_skills: Vec<String> = Vec::new();
-
-                    let spell_grade = calculate_grade(member, "Int", "Occ");
-                    let mut spell_skills: Vec<String> = Vec::new();
-
-                    if let Some(&Property::List(ref v)) = member.borrow().get("SkillPoints") {
-                        for (i, ref val) in v.iter().enumerate() {
-                            match (i, val.parse::<u32>()) {
-                                // Combat skills (1-3)
-                                (7...61, Ok(n)) if n > 0 => {
-                                    if let Some(ref skill) = skills.get(&i) {
-                                        combat_skills.push(skill.internal.to_string())
-                                    }
-                                }
-                                // Spell skills (1)
-                                (62...72, Ok(n)) if n > 0 || spell_grade >= 1.0 => {
-                                    if let Some(ref skill) = skills.get(&i) {
-                                        spell_skills.push(skill.internal.to_string())
-                                    }
-                                }
-                                // Spell skills (2)
-                                (73...86, Ok(n)) if n > 0 || spell_grade >= 2.0 => {
-                                    if let Some(ref skill) = skills.get(&i) {
-                                        spell_skills.push(skill.internal.to_string())
-                                    }
-                                }
-                                // Spell skills (3)
-                                (87...114, Ok(n)) if n > 0 || spell_grade >= 3.0 => {
-                                    if let Some(ref skill) = skills.get(&i) {
-                                        spell_skills.push(skill.internal.to_string())
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                    if let Some(&mut Property::List(ref mut v)) = member.borrow_mut().get_mut("CombatSelects") {
-                        v.retain(|ref x| combat_skills.contains(&x));
-                        while v.len() < 3 {
-                            v.push("Empty".to_string());
-                        }
-                    }
-                    if let Some(&mut Property::List(ref mut v)) = member.borrow_mut().get_mut("SpellFavorites") {
-                        v.retain(|ref x| spell_skills.contains(&x));
-                        while v.len() < 10 {
-                            v.push("".to_string());
-                        }
+                // Refuse to save a party the game could not load, and report anything else
+                // it would not normally produce.
+                let report = {
+                    let party = party_clone.borrow();
+                    let members: Vec<Ref<PropertyMap>> = party.iter().map(|&(_, ref m)| m.borrow()).collect();
+                    let members: Vec<&PropertyMap> = members.iter().map(|m| &**m).collect();
+                    rules::validate(&game_clone.borrow(), &members, &skills)
+                };
+                if !report.is_empty() && !show_report(&report) {
+                    return;
+                }
+
+                // Refuse to overwrite any file that does not survive a round-trip unchanged;
+                // the edited data would not be encoded faithfully either. Damaged files that
+                // were recovered cannot be verified, but are replaced all the same.
+                let mut paths = vec![save::game_path(Path::new(&dir))];
+                paths.extend(party_clone.borrow().iter().map(|&(ref path, _)| path.clone()));
+                for path in paths.iter().filter(|&p| !recovered.borrow().contains(p)) {
+                    if let Err(e) = save::verify(path.as_path()) {
+                        log!("{}", e);
+                        show_message("Save failed", &format!("{}\n\nNo files were modified.", e));
+                        return;
                     }
-                    member.borrow_mut().insert("CombatGrade".to_string(), Property::Float(combat_grade));
-                    member.borrow_mut().insert("CombatSkills".to_string(), Property::List(combat_skills));
-                    member.borrow_mut().insert("SpellGrade".to_string(), Property::Float(spell_grade));
-                    member.borrow_mut().insert("SpellSkills".to_string(), Property::List(spell_skills));
-
-                    if let Some(&Property::String(ref id)) = member.borrow().get("PartyID") {
-                        let path = Path::new(&dir).join("Party".to_string() + id + ".txt");
-                        copy(path.as_path(), path.with_extension(&timestamp)).unwrap();
-                        write_path(path.as_path(), &member.borrow()).unwrap();
+                }
+
+                let timestamp = save::timestamp();
+                let path = save::game_path(Path::new(&dir));
+                let mut res = save::write_with_backup(path.as_path(), &game_clone.borrow(), &timestamp);
+                for &(ref path, ref member) in party_clone.borrow().iter() {
+                    if res.is_err() {
+                        break;
                     }
+                    rules::normalize(&mut member.borrow_mut(), &skills);
+                    res = save::write_with_backup(path.as_path(), &member.borrow(), &timestamp);
+                }
+                match res {
+                    Ok(..) => recovered.borrow_mut().clear(),
+                    Err(e) => {
+                        log!("{}", e);
+                        show_message("Save failed", &format!(
+                            "{}\n\nFiles already written were backed up with the timestamp {}.", e, timestamp));
+                    },
                 }
             });
         }
@@ -368,39 +1010,3 @@ pub fn ui_loop() -> Result<(), String> {
         _ => Ok(())
     }
 }
-
-struct Skill {
-    name: String,
-    internal: String,
-}
-
-fn load_skills() -> HashMap<usize, Skill> {
-    let mut skills: HashMap<usize, Skill> = HashMap::with_capacity(115);
-    if let Ok(elem) = include_str!("../resources/skills.xml").parse::<xml::Element>() {
-        for child in elem.get_children("skill", None) {
-            let name = child.get_children("name", None).nth(0).map(|ref e| e.content_str());
-            let desc = child.get_children("description", None).nth(0).map(|ref e| e.content_str());
-            match name {
-                Some(ref name) if !name.is_empty() => {
-                    match desc {
-                        Some(ref desc) if !desc.is_empty() => {
-                            let id = child.attributes
-                                .get(&("number".to_string(), None)).unwrap()
-                                .parse::<usize>()
-                                .unwrap();
-                            let internal = child.attributes
-                                .get(&("spritename".to_string(), None)).unwrap_or(name);
-                            skills.insert(id, Skill {
-                                name: name.to_owned(),
-                                internal: internal.to_owned(),
-                            });
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-    skills
-}